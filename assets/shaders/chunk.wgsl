@@ -0,0 +1,61 @@
+#import bevy_pbr::mesh_view_bind_group
+#import bevy_pbr::mesh_struct
+
+[[group(1), binding(0)]]
+var texture: texture_2d<f32>;
+[[group(1), binding(1)]]
+var texture_sampler: sampler;
+
+struct ChunkMaterial {
+    fog_color: vec4<f32>;
+    daylight: f32;
+    fog_start: f32;
+    fog_end: f32;
+};
+[[group(1), binding(2)]]
+var<uniform> material: ChunkMaterial;
+
+[[group(2), binding(0)]]
+var<uniform> mesh: Mesh;
+
+struct Vertex {
+    [[location(0)]] position: vec3<f32>;
+    [[location(1)]] normal: vec3<f32>;
+    [[location(2)]] uv: vec2<f32>;
+    [[location(3)]] color: vec4<f32>;
+    [[location(4)]] ambient_occlusion: f32;
+};
+
+struct VertexOutput {
+    [[builtin(position)]] clip_position: vec4<f32>;
+    [[location(0)]] uv: vec2<f32>;
+    [[location(1)]] color: vec4<f32>;
+    [[location(2)]] ambient_occlusion: f32;
+    [[location(3)]] world_position: vec3<f32>;
+};
+
+[[stage(vertex)]]
+fn vertex(vertex: Vertex) -> VertexOutput {
+    let world_position = mesh.model * vec4<f32>(vertex.position, 1.0);
+    var out: VertexOutput;
+    out.clip_position = view.view_proj * world_position;
+    out.uv = vertex.uv;
+    out.color = vertex.color;
+    out.ambient_occlusion = vertex.ambient_occlusion;
+    out.world_position = world_position.xyz;
+    return out;
+}
+
+// The light is baked into the vertex colours by the meshing, the block light in the RGB
+// channels and the sky light in the alpha channel, dimmed by the time of day. The ambient
+// occlusion darkens the corners next to other blocks, and the far meshes fade into the fog.
+[[stage(fragment)]]
+fn fragment(in: VertexOutput) -> [[location(0)]] vec4<f32> {
+    let color = textureSample(texture, texture_sampler, in.uv);
+    let light = max(in.color.rgb, vec3<f32>(in.color.a * material.daylight));
+    let distance = length(in.world_position - view.world_position);
+    let fog_length = max(material.fog_end - material.fog_start, 0.001);
+    let fog = clamp((distance - material.fog_start) / fog_length, 0.0, 1.0);
+    let lit = color.rgb * light * in.ambient_occlusion;
+    return vec4<f32>(mix(lit, material.fog_color.rgb, fog), color.a);
+}
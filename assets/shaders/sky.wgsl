@@ -0,0 +1,64 @@
+#import bevy_pbr::mesh_view_bind_group
+#import bevy_pbr::mesh_struct
+
+struct SkyMaterial {
+    zenith: vec4<f32>;
+    horizon: vec4<f32>;
+    sunrise: vec4<f32>;
+    sun_color: vec4<f32>;
+    sun_direction: vec3<f32>;
+    stars: f32;
+    fog: vec4<f32>;
+};
+[[group(1), binding(0)]]
+var<uniform> material: SkyMaterial;
+
+[[group(2), binding(0)]]
+var<uniform> mesh: Mesh;
+
+struct Vertex {
+    [[location(0)]] position: vec3<f32>;
+};
+
+struct VertexOutput {
+    [[builtin(position)]] clip_position: vec4<f32>;
+    [[location(0)]] world_position: vec3<f32>;
+};
+
+[[stage(vertex)]]
+fn vertex(vertex: Vertex) -> VertexOutput {
+    let world_position = mesh.model * vec4<f32>(vertex.position, 1.0);
+    var out: VertexOutput;
+    out.clip_position = view.view_proj * world_position;
+    out.world_position = world_position.xyz;
+    return out;
+}
+
+fn hash(cell: vec3<f32>) -> f32 {
+    return fract(sin(dot(cell, vec3<f32>(12.9898, 78.233, 37.719))) * 43758.5453);
+}
+
+// The sky is coloured by the direction it is seen in, from the horizon up to the zenith.
+[[stage(fragment)]]
+fn fragment(in: VertexOutput) -> [[location(0)]] vec4<f32> {
+    let direction = normalize(in.world_position - view.world_position);
+    let height = max(direction.y, 0.0);
+    var color = mix(material.horizon.rgb, material.zenith.rgb, sqrt(height));
+
+    // Sunrise and sunset glow around the sun, spreading along the horizon.
+    let sun = dot(direction, material.sun_direction);
+    let glow = pow(max(sun, 0.0), 6.0) * (1.0 - height * 0.7) + pow(1.0 - height, 8.0) * 0.3;
+    color = color + material.sunrise.rgb * glow;
+
+    // Stars are fixed to the sky cells of the directions, above the horizon.
+    let cell = floor(direction * 300.0);
+    let star = step(0.9985, hash(cell)) * hash(cell + vec3<f32>(1.0)) * smoothstep(0.0, 0.1, direction.y);
+    color = color + vec3<f32>(star * material.stars);
+
+    // Sun disc, and the moon on the other side.
+    let sun_disc = smoothstep(0.9994, 0.9997, sun);
+    color = mix(color, material.sun_color.rgb * 4.0, sun_disc);
+    let moon_disc = smoothstep(0.9996, 0.9998, -sun);
+    color = mix(color, vec3<f32>(0.8, 0.85, 0.95), moon_disc * material.stars);
+    return vec4<f32>(mix(color, material.fog.rgb, material.fog.a), 1.0);
+}
@@ -0,0 +1,62 @@
+use building_blocks::core::{Extent2i, Extent3i, PointN};
+use building_blocks::prelude::{Array2x1, Array3x1, GetMut};
+use criterion::{black_box, criterion_group, criterion_main, Criterion};
+use noise::{Fbm, MultiFractal, NoiseFn};
+
+use rusted_terra::chunk::SubSampleNoise;
+
+fn sub_sample_2d(c: &mut Criterion) {
+    let fbm = Fbm::new().set_octaves(8);
+    let noise = SubSampleNoise::new(&fbm)
+        .set_scale([0.004, 0.004, 1.0])
+        .set_sample_rate(4);
+    let extent = Extent2i::from_min_and_shape(PointN([-16; 2]), PointN([32; 2]));
+    let mut array = Array2x1::fill(extent, 0.0);
+
+    let mut group = c.benchmark_group("sub_sample_noise_32x32");
+    group.bench_function("get", |b| {
+        b.iter(|| {
+            for pos in extent.iter_points() {
+                *array.get_mut(pos) = noise.get([pos.x() as f64, pos.y() as f64]);
+            }
+            black_box(&array);
+        })
+    });
+    group.bench_function("fill", |b| {
+        b.iter(|| {
+            noise.fill(&mut array);
+            black_box(&array);
+        })
+    });
+    group.finish();
+}
+
+fn sub_sample_3d(c: &mut Criterion) {
+    let fbm = Fbm::new().set_octaves(8);
+    let noise = SubSampleNoise::new(&fbm)
+        .set_scale([0.005, 0.007, 0.005])
+        .set_sample_rate(4);
+    let extent = Extent3i::from_min_and_shape(PointN([-16; 3]), PointN([32; 3]));
+    let mut array = Array3x1::fill(extent, 0.0);
+
+    let mut group = c.benchmark_group("sub_sample_noise_32x32x32");
+    group.sample_size(20);
+    group.bench_function("get", |b| {
+        b.iter(|| {
+            for pos in extent.iter_points() {
+                *array.get_mut(pos) = noise.get([pos.x() as f64, pos.y() as f64, pos.z() as f64]);
+            }
+            black_box(&array);
+        })
+    });
+    group.bench_function("fill", |b| {
+        b.iter(|| {
+            noise.fill(&mut array);
+            black_box(&array);
+        })
+    });
+    group.finish();
+}
+
+criterion_group!(benches, sub_sample_2d, sub_sample_3d);
+criterion_main!(benches);
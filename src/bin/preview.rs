@@ -0,0 +1,301 @@
+//! Renders maps of the generated world to PNGs without opening a window.
+//!
+//! ```text
+//! cargo run --bin preview -- [--seed SEED] [--preset PATH] [--bounds MIN_X,MIN_Z,MAX_X,MAX_Z] [--out DIR]
+//! ```
+
+use std::fs;
+use std::path::PathBuf;
+use std::process;
+
+use anyhow::{bail, Context};
+use bevy::prelude::*;
+use building_blocks::core::{Extent3i, PointN};
+use building_blocks::prelude::Get;
+use image::{Rgb, RgbImage};
+
+use rusted_terra::chunk::{
+    generate_area, ChunkGeneratorPlugin, ElevationFacet, FacetRegistry, GeneratorResources,
+    SeaLevel, Seed, SurfaceRoughnessFacet, WaterLevelFacet, WorldPreset,
+};
+
+/// Voxels from this height up are rasterized to find the top block of each column.
+const MIN_HEIGHT: i32 = 0;
+const MAX_HEIGHT: i32 = 256;
+/// Columns are generated in tiles of this size.
+const TILE: i32 = 32;
+
+struct Args {
+    seed: Option<Seed>,
+    preset: Option<PathBuf>,
+    /// Minimum and maximum columns, both included.
+    bounds: [i32; 4],
+    out: PathBuf,
+}
+
+fn usage() -> ! {
+    eprintln!(
+        "Usage: preview [--seed SEED] [--preset PATH] [--bounds MIN_X,MIN_Z,MAX_X,MAX_Z] [--out DIR]"
+    );
+    process::exit(2)
+}
+
+fn parse_args() -> anyhow::Result<Args> {
+    let mut args = Args {
+        seed: None,
+        preset: None,
+        bounds: [-256, -256, 255, 255],
+        out: PathBuf::from("preview"),
+    };
+    let mut iter = std::env::args().skip(1);
+    while let Some(arg) = iter.next() {
+        let mut value = || {
+            iter.next()
+                .with_context(|| format!("{} needs a value", arg))
+        };
+        match arg.as_str() {
+            "--seed" => args.seed = Some(value()?.parse().context("Invalid seed")?),
+            "--preset" => args.preset = Some(PathBuf::from(value()?)),
+            "--bounds" => {
+                let bounds = value()?
+                    .split(',')
+                    .map(|v| v.trim().parse::<i32>())
+                    .collect::<Result<Vec<_>, _>>()
+                    .context("Invalid bounds")?;
+                if bounds.len() != 4 || bounds[0] > bounds[2] || bounds[1] > bounds[3] {
+                    bail!("Bounds have to be MIN_X,MIN_Z,MAX_X,MAX_Z");
+                }
+                args.bounds = [bounds[0], bounds[1], bounds[2], bounds[3]];
+            }
+            "--out" => args.out = PathBuf::from(value()?),
+            "--help" | "-h" => usage(),
+            _ => bail!("Unknown argument {}", arg),
+        }
+    }
+    Ok(args)
+}
+
+/// Per column values of the previewed rectangle, row by row.
+struct Map {
+    min: [i32; 2],
+    width: usize,
+    height: usize,
+    sea_level: i32,
+    elevation: Vec<i32>,
+    roughness: Vec<f32>,
+    water_level: Vec<i32>,
+    /// Voxel id of the topmost block, 0 if the column is empty.
+    top: Vec<u8>,
+}
+
+impl Map {
+    fn index(&self, x: i32, z: i32) -> usize {
+        (z - self.min[1]) as usize * self.width + (x - self.min[0]) as usize
+    }
+
+    /// Elevation with the coordinates clamped to the map.
+    fn elevation_at(&self, x: i32, z: i32) -> i32 {
+        let x = x.clamp(self.min[0], self.min[0] + self.width as i32 - 1);
+        let z = z.clamp(self.min[1], self.min[1] + self.height as i32 - 1);
+        self.elevation[self.index(x, z)]
+    }
+
+    fn render(&self, pixel: impl Fn(i32, i32, usize) -> [u8; 3]) -> RgbImage {
+        RgbImage::from_fn(self.width as u32, self.height as u32, |px, pz| {
+            let (x, z) = (self.min[0] + px as i32, self.min[1] + pz as i32);
+            Rgb(pixel(x, z, self.index(x, z)))
+        })
+    }
+}
+
+fn generate(registry: &FacetRegistry, resources: &GeneratorResources, bounds: [i32; 4]) -> Map {
+    let [min_x, min_z, max_x, max_z] = bounds;
+    let width = (max_x - min_x + 1) as usize;
+    let height = (max_z - min_z + 1) as usize;
+    let mut map = Map {
+        min: [min_x, min_z],
+        width,
+        height,
+        sea_level: 0,
+        elevation: vec![0; width * height],
+        roughness: vec![0.0; width * height],
+        water_level: vec![0; width * height],
+        top: vec![0; width * height],
+    };
+
+    let tiles = ((max_x - min_x) / TILE + 1) * ((max_z - min_z) / TILE + 1);
+    let mut done = 0;
+    for tile_z in (min_z..=max_z).step_by(TILE as usize) {
+        for tile_x in (min_x..=max_x).step_by(TILE as usize) {
+            let shape_x = TILE.min(max_x - tile_x + 1);
+            let shape_z = TILE.min(max_z - tile_z + 1);
+            let area = Extent3i::from_min_and_shape(
+                PointN([tile_x, MIN_HEIGHT, tile_z]),
+                PointN([shape_x, MAX_HEIGHT - MIN_HEIGHT, shape_z]),
+            );
+            let (facets, chunk) = generate_area(registry, resources, area);
+            let elevation = &facets.get::<ElevationFacet>().unwrap().0;
+            let roughness = &facets.get::<SurfaceRoughnessFacet>().unwrap().0;
+            let water_level = &facets.get::<WaterLevelFacet>().unwrap().0;
+            map.sea_level = facets.get::<SeaLevel>().unwrap().0;
+
+            for z in tile_z..tile_z + shape_z {
+                for x in tile_x..tile_x + shape_x {
+                    let i = map.index(x, z);
+                    let column = PointN([x, z]);
+                    map.elevation[i] = elevation.data.get(column);
+                    map.roughness[i] = roughness.data.get(column);
+                    map.water_level[i] = water_level.data.get(column);
+                    map.top[i] = (MIN_HEIGHT..MAX_HEIGHT)
+                        .rev()
+                        .map(|y| chunk.data().get(PointN([x, y, z])).0)
+                        .find(|voxel| *voxel != 0)
+                        .unwrap_or(0);
+                }
+            }
+            done += 1;
+            eprint!("\rGenerated {}/{} tiles", done, tiles);
+        }
+    }
+    eprintln!();
+    map
+}
+
+fn lerp_color(a: [u8; 3], b: [u8; 3], t: f32) -> [u8; 3] {
+    let t = t.clamp(0.0, 1.0);
+    let mix = |a: u8, b: u8| (a as f32 + (b as f32 - a as f32) * t) as u8;
+    [mix(a[0], b[0]), mix(a[1], b[1]), mix(a[2], b[2])]
+}
+
+fn shade(color: [u8; 3], light: f32) -> [u8; 3] {
+    let light = light.clamp(0.0, 2.0);
+    color.map(|c| (c as f32 * light).min(255.0) as u8)
+}
+
+/// Light of a column lit from the north west.
+fn hill_shade(map: &Map, x: i32, z: i32) -> f32 {
+    let dx = map.elevation_at(x + 1, z) - map.elevation_at(x - 1, z);
+    let dz = map.elevation_at(x, z + 1) - map.elevation_at(x, z - 1);
+    1.0 - (dx + dz) as f32 * 0.08
+}
+
+fn elevation_image(map: &Map) -> RgbImage {
+    let low = map.sea_level;
+    let high = map
+        .elevation
+        .iter()
+        .copied()
+        .max()
+        .unwrap_or(low)
+        .max(low + 1);
+    map.render(|x, z, i| {
+        let t = (map.elevation[i] - low) as f32 / (high - low) as f32;
+        let gray = (t.clamp(0.0, 1.0) * 200.0) as u8 + 40;
+        shade([gray; 3], hill_shade(map, x, z))
+    })
+}
+
+fn roughness_image(map: &Map) -> RgbImage {
+    map.render(|_, _, i| {
+        let value = (map.roughness[i] / 2.0).clamp(0.0, 1.0);
+        [(value * 255.0) as u8; 3]
+    })
+}
+
+/// There are no biomes yet, columns are classified by height, water and roughness.
+fn biome_image(map: &Map) -> RgbImage {
+    map.render(|x, z, i| {
+        let elevation = map.elevation[i];
+        let water_level = map.water_level[i];
+        let height = elevation - map.sea_level;
+        let color = if water_level > elevation {
+            if water_level > map.sea_level {
+                [70, 140, 220] // River or lake
+            } else {
+                [30, 60, 150] // Ocean
+            }
+        } else if height <= 2 {
+            [220, 205, 140] // Beach
+        } else if height > 56 {
+            [240, 240, 245] // Snow
+        } else if map.roughness[i] > 1.0 {
+            [120, 115, 110] // Mountains
+        } else if height > 28 {
+            [70, 120, 60] // Hills
+        } else {
+            [100, 170, 70] // Plains
+        };
+        shade(color, hill_shade(map, x, z))
+    })
+}
+
+fn top_block_image(map: &Map) -> RgbImage {
+    map.render(|x, z, i| {
+        // Voxel ids written by the rasterizer.
+        let color = match map.top[i] {
+            0 => [0, 0, 0],
+            1 => [120, 85, 55],   // Dirt
+            2 => [125, 125, 125], // Stone
+            4 => [50, 90, 200],   // Water
+            _ => [255, 0, 255],
+        };
+        shade(color, hill_shade(map, x, z))
+    })
+}
+
+fn water_depth_image(map: &Map) -> RgbImage {
+    map.render(|_, _, i| {
+        let depth = map.water_level[i] - map.elevation[i];
+        if depth > 0 {
+            lerp_color([150, 210, 255], [0, 20, 90], depth as f32 / 32.0)
+        } else {
+            [0, 0, 0]
+        }
+    })
+}
+
+fn run() -> anyhow::Result<()> {
+    let args = parse_args()?;
+    let mut preset = match &args.preset {
+        Some(path) => WorldPreset::load(path)
+            .with_context(|| format!("Cannot load preset {}", path.display()))?,
+        None => WorldPreset::default(),
+    };
+    if let Some(seed) = args.seed {
+        preset.seed = seed;
+    }
+
+    // Startup systems load the preset's heightmap and noise graphs and schedule the facets.
+    let mut app = App::new();
+    app.add_plugins(MinimalPlugins)
+        .insert_resource(preset)
+        .add_plugin(ChunkGeneratorPlugin);
+    app.update();
+    let registry = app.world.get_resource::<FacetRegistry>().unwrap();
+    let resources = app.world.get_resource::<GeneratorResources>().unwrap();
+
+    let map = generate(registry, resources, args.bounds);
+    fs::create_dir_all(&args.out)?;
+    let images = [
+        ("elevation.png", elevation_image(&map)),
+        ("roughness.png", roughness_image(&map)),
+        ("biome.png", biome_image(&map)),
+        ("top_block.png", top_block_image(&map)),
+        ("water_depth.png", water_depth_image(&map)),
+    ];
+    for (name, image) in images.iter() {
+        let path = args.out.join(name);
+        image
+            .save(&path)
+            .with_context(|| format!("Cannot write {}", path.display()))?;
+        println!("Wrote {}", path.display());
+    }
+    Ok(())
+}
+
+fn main() {
+    if let Err(e) = run() {
+        eprintln!("{:?}", e);
+        process::exit(1);
+    }
+}
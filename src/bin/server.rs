@@ -0,0 +1,78 @@
+//! Dedicated server: loads blocks and generates chunks without a window or a GPU.
+//!
+//! ```text
+//! cargo run --bin server -- [--bind ADDRESS] [--preset PATH] [--seed SEED]
+//! ```
+//!
+//! The seed replaces the one of the preset, clients are told it when joining.
+
+use std::time::Duration;
+
+use bevy::app::ScheduleRunnerSettings;
+use bevy::asset::AssetPlugin;
+use bevy::log::LogPlugin;
+use bevy::prelude::*;
+
+use rusted_terra::chunk::{
+    self, BlockTickPlugin, ChunkGeneratorPlugin, FallingBlockPlugin, GrassPlugin, LiquidPlugin,
+    Relative, Seed, WorldPreset,
+};
+use rusted_terra::net::{ServerPlugin, ServerSettings, TICK_RATE};
+use rusted_terra::{blocks, AppState, LoadingPlugin};
+
+fn main() {
+    let mut settings = ServerSettings::default();
+    let mut preset = WorldPreset::default();
+    let mut seed = None;
+    let mut args = std::env::args().skip(1);
+    while let Some(arg) = args.next() {
+        match (arg.as_str(), args.next()) {
+            ("--bind", Some(address)) => match address.parse() {
+                Ok(address) => settings.address = address,
+                Err(e) => panic!("Invalid address {}: {}", address, e),
+            },
+            ("--preset", Some(path)) => match WorldPreset::load(&path) {
+                Ok(loaded) => preset = loaded,
+                Err(e) => panic!("Cannot load preset {}: {:?}", path, e),
+            },
+            ("--seed", Some(value)) => match value.parse::<Seed>() {
+                Ok(value) => seed = Some(value),
+                Err(e) => panic!("Invalid seed {}: {}", value, e),
+            },
+            _ => panic!("Usage: server [--bind ADDRESS] [--preset PATH] [--seed SEED]"),
+        }
+    }
+    if let Some(seed) = seed {
+        preset.seed = seed;
+    }
+
+    App::new()
+        .insert_resource(settings)
+        .insert_resource(preset)
+        .insert_resource(ScheduleRunnerSettings::run_loop(Duration::from_secs_f64(
+            1.0 / TICK_RATE,
+        )))
+        .add_plugins(MinimalPlugins)
+        .add_plugin(LogPlugin)
+        .add_plugin(AssetPlugin)
+        .add_plugin(LoadingPlugin)
+        .add_system_set(SystemSet::on_enter(AppState::Run).with_system(setup))
+        .add_plugin(chunk::ChunkPlugin)
+        .add_plugin(blocks::BlockPlugin)
+        .add_plugin(ChunkGeneratorPlugin)
+        .add_plugin(BlockTickPlugin)
+        .add_plugin(LiquidPlugin)
+        .add_plugin(FallingBlockPlugin)
+        .add_plugin(GrassPlugin)
+        .add_plugin(ServerPlugin)
+        .run();
+}
+
+/// Keeps the chunks around the spawn generated.
+fn setup(mut commands: Commands) {
+    info!("Server is running");
+    commands
+        .spawn()
+        .insert(Transform::default())
+        .insert(Relative([2; 3]));
+}
@@ -7,6 +7,7 @@ use bevy::render::render_resource::{AddressMode, SamplerDescriptor};
 use bevy::utils::HashMap;
 use serde_derive::Deserialize;
 
+use crate::chunk::Voxel;
 use crate::{AppState, Loading};
 
 pub type BlockId = u32;
@@ -21,9 +22,12 @@ impl Plugin for BlockPlugin {
             .init_asset_loader::<BlockLoader>()
             .init_resource::<Blocks>()
             .init_resource::<BlockLoading>()
-            .add_system(block_materials)
             .add_system_set(SystemSet::on_enter(AppState::Loading).with_system(load_all))
             .add_system_set(SystemSet::on_exit(AppState::Loading).with_system(loaded));
+        // Headless apps have no images, block textures are not loaded there.
+        if app.world.contains_resource::<Assets<Image>>() {
+            app.add_system(block_materials);
+        }
     }
 }
 
@@ -34,13 +38,16 @@ pub struct Blocks {
 }
 
 impl Blocks {
+    /// Adds the block with the lowest id which is not taken.
     pub fn add_block(&mut self, handle: Handle<Block>) {
+        while self.map.contains_key(&self.next_id) {
+            self.next_id += 1;
+        }
         self.map.insert(self.next_id, handle);
         self.next_id += 1;
     }
     pub fn insert_block(&mut self, id: BlockId, handle: Handle<Block>) {
         self.map.insert(id, handle);
-        self.next_id = id + 1;
     }
     pub fn get_block(&self, assets: &Assets<Block>, id: &BlockId) -> Option<Block> {
         let handle = self.map.get(id)?;
@@ -69,18 +76,48 @@ fn load_all(
 }
 
 fn loaded(
+    asset_server: Res<AssetServer>,
     assets_blocks: Res<Assets<Block>>,
     mut blocks: ResMut<Blocks>,
     blocks_loaded: Res<BlockLoading>,
 ) {
-    for handle in &blocks_loaded.0 {
-        let handle = assets_blocks.get_handle(handle);
+    let mut handles: Vec<Handle<Block>> = blocks_loaded
+        .0
+        .iter()
+        .map(|handle| assets_blocks.get_handle(handle))
+        .collect();
+    // Sorted so that the blocks without a fixed id get the same one on every run.
+    handles.sort_by_key(|handle| {
+        asset_server
+            .get_handle_path(handle)
+            .map(|path| path.path().to_owned())
+    });
+    let (fixed, free): (Vec<_>, Vec<_>) = handles.into_iter().partition(|handle| {
+        assets_blocks
+            .get(handle)
+            .and_then(|block| block.id)
+            .is_some()
+    });
+    for handle in fixed {
+        let id = assets_blocks.get(&handle).unwrap().id.unwrap();
+        blocks.insert_block(id, handle);
+    }
+    for handle in free {
         blocks.add_block(handle);
     }
 }
 
-#[derive(Default)]
-struct BlockLoader;
+struct BlockLoader {
+    load_textures: bool,
+}
+
+impl FromWorld for BlockLoader {
+    fn from_world(world: &mut World) -> Self {
+        BlockLoader {
+            load_textures: world.contains_resource::<Assets<Image>>(),
+        }
+    }
+}
 
 impl AssetLoader for BlockLoader {
     fn load<'a>(
@@ -90,10 +127,17 @@ impl AssetLoader for BlockLoader {
     ) -> BoxedFuture<'a, anyhow::Result<(), anyhow::Error>> {
         Box::pin(async move {
             let str = String::from_utf8_lossy(bytes);
-            let block: Block = ron::from_str(str.borrow())?;
+            let mut block: Block = ron::from_str(str.borrow())?;
+            let file_name = load_context.path().file_name().unwrap_or_default();
+            block.name = file_name
+                .to_string_lossy()
+                .trim_end_matches(".block.ron")
+                .to_owned();
             let texture_name = &*block.texture_name.clone();
             let mut asset = LoadedAsset::new(block);
-            asset.add_dependency(texture_name.into());
+            if self.load_textures {
+                asset.add_dependency(texture_name.into());
+            }
             load_context.set_default_asset(asset);
             Ok(())
         })
@@ -107,9 +151,101 @@ impl AssetLoader for BlockLoader {
 #[derive(Debug, Clone, TypeUuid, Deserialize)]
 #[uuid = "8e70a904-cb5f-447d-98e7-d22d63c1a5e7"]
 pub struct Block {
+    /// File name without the extension, which behaviours are registered against.
+    #[serde(skip)]
+    pub name: String,
+    /// Id the block is stored as in the voxels (minus one), picked when loading if missing.
+    #[serde(default)]
+    pub id: Option<BlockId>,
     pub texture_name: String,
     pub liquid: bool,
     pub opaque: bool,
+    /// Falls when the block below is not solid.
+    #[serde(default)]
+    pub gravity: bool,
+    /// Light the block gives off, up to `MAX_LIGHT`.
+    #[serde(default)]
+    pub light_emission: u8,
+    /// How entities move through the block, only used by liquids.
+    #[serde(default)]
+    pub fluid: Fluid,
+}
+
+#[derive(Debug, Clone, Deserialize)]
+#[serde(default)]
+pub struct Fluid {
+    /// Part of the gravity pushed back up when fully submerged, floating above 1.
+    pub buoyancy: f32,
+    /// Fraction of the velocity lost per second when fully submerged.
+    pub drag: f32,
+    /// Walking speed multiplier when fully submerged.
+    pub speed: f32,
+    /// Upward speed when swimming up.
+    pub swim_speed: f32,
+    /// Color drawn over the screen while the eyes are inside, its alpha is the strength.
+    pub tint: (f32, f32, f32, f32),
+    /// How many blocks the liquid flows sideways from a source, at most `Voxel::MAX_LEVEL`.
+    pub spread: u8,
+}
+
+impl Default for Fluid {
+    fn default() -> Self {
+        Fluid {
+            buoyancy: 1.0,
+            drag: 2.0,
+            speed: 0.5,
+            swim_speed: 3.0,
+            tint: (0.1, 0.3, 0.6, 0.5),
+            spread: Voxel::MAX_LEVEL,
+        }
+    }
+}
+
+/// Blocks indexed by the block part of voxels, to look them up in tight loops.
+pub struct VoxelBlocks(Vec<Option<Block>>);
+
+impl VoxelBlocks {
+    pub fn new(blocks: &Blocks, block_assets: &Assets<Block>) -> Self {
+        VoxelBlocks(
+            (0..32)
+                .map(|block| Voxel::new(block, 0).block_id())
+                .map(|id| id.and_then(|id| blocks.get_block(block_assets, &id)))
+                .collect(),
+        )
+    }
+
+    pub fn get(&self, voxel: Voxel) -> Option<&Block> {
+        self.0[voxel.block() as usize].as_ref()
+    }
+
+    /// Whether the voxel can be stood on, unknown blocks are.
+    pub fn is_solid(&self, voxel: Voxel) -> bool {
+        voxel.block() != 0 && self.get(voxel).map_or(true, |block| !block.liquid)
+    }
+
+    /// Whether the voxel is air or a known block, with a level only for liquids.
+    pub fn is_valid(&self, voxel: Voxel) -> bool {
+        match self.get(voxel) {
+            _ if voxel.level() > Voxel::MAX_LEVEL => false,
+            Some(block) => block.liquid || voxel.level() == 0,
+            None => voxel == Voxel::AIR,
+        }
+    }
+
+    /// Voxel of the block called `name`.
+    pub fn find(&self, name: &str) -> Option<Voxel> {
+        let block = self
+            .0
+            .iter()
+            .position(|block| block.as_ref().map_or(false, |block| block.name == name))?;
+        Some(Voxel::new(block as u8, 0))
+    }
+
+    pub fn fluid(&self, voxel: Voxel) -> Option<&Fluid> {
+        self.get(voxel)
+            .filter(|block| block.liquid)
+            .map(|block| &block.fluid)
+    }
 }
 
 pub fn block_materials(
@@ -0,0 +1,217 @@
+use bevy::prelude::*;
+use building_blocks::core::{Point3i, PointN};
+use building_blocks::prelude::{Get, GetMut};
+
+use crate::blocks::{Block, Blocks, VoxelBlocks};
+use crate::chunk::{
+    voxel_chunk, BlockBehaviour, BlockBehaviourAppExt, BlockWorld, Chunk, ChunkWorld, Voxel,
+    VoxelChanged,
+};
+
+/// Ticks between a block losing its support and it falling.
+const FALL_DELAY: u64 = 2;
+const GRAVITY: f32 = 28.0;
+const MAX_FALL_SPEED: f32 = 50.0;
+
+const DOWN: Point3i = PointN([0, -1, 0]);
+
+/// Block with gravity which left the chunks until it lands, its `Transform` is at its minimum.
+#[derive(Component)]
+pub struct FallingBlock {
+    pub voxel: Voxel,
+    /// Downward speed.
+    pub velocity: f32,
+}
+
+impl FallingBlock {
+    /// Spawns `voxel` falling from `pos`, which has to be emptied by the caller.
+    pub fn spawn(commands: &mut Commands, pos: Point3i, voxel: Voxel) -> Entity {
+        commands
+            .spawn()
+            .insert(FallingBlock {
+                voxel,
+                velocity: 0.0,
+            })
+            .insert(Transform::from_xyz(
+                pos.x() as f32,
+                pos.y() as f32,
+                pos.z() as f32,
+            ))
+            .insert(GlobalTransform::default())
+            .id()
+    }
+}
+
+/// Falling block of a client, which only rests where it lands until the server places it.
+#[derive(Component)]
+pub struct RemoteFallingBlock;
+
+/// Moves and shows the falling blocks. Blocks with gravity only start falling with
+/// `BlockTickPlugin`, clients without it simulate the falling blocks the server sends them.
+pub struct FallingBlockPlugin;
+
+impl Plugin for FallingBlockPlugin {
+    fn build(&self, app: &mut App) {
+        app.add_block_behaviour_for(|block| block.gravity, Falls)
+            .add_system(fall)
+            .add_system(falling_meshes);
+    }
+}
+
+/// Makes a block fall as a `FallingBlock` when the block below is not solid.
+pub struct Falls;
+
+impl BlockBehaviour for Falls {
+    fn neighbour_changed(&self, world: &mut BlockWorld, pos: Point3i) {
+        world.schedule(pos, FALL_DELAY, 0);
+    }
+
+    fn scheduled_tick(&self, world: &mut BlockWorld, pos: Point3i) {
+        let voxel = match world.get(pos) {
+            Some(voxel) if unsupported(world, pos) => voxel,
+            _ => return,
+        };
+
+        world.set(pos, Voxel::AIR);
+        FallingBlock::spawn(world.commands(), pos, voxel);
+    }
+
+    fn generated(&self, world: &mut BlockWorld, pos: Point3i) {
+        if unsupported(world, pos) {
+            world.schedule(pos, FALL_DELAY, 0);
+        }
+    }
+}
+
+/// Blocks above chunks which are not generated yet wait for them.
+fn unsupported(world: &BlockWorld, pos: Point3i) -> bool {
+    world
+        .get(pos + DOWN)
+        .map_or(false, |below| !world.blocks().is_solid(below))
+}
+
+/// Moves the falling blocks down and puts them back into the chunks on the first solid block,
+/// they wait above the chunks which are not loaded. Remote ones are left to the server.
+#[allow(clippy::too_many_arguments)]
+fn fall(
+    mut commands: Commands,
+    time: Res<Time>,
+    world: Res<ChunkWorld>,
+    mut chunks: Query<&mut Chunk>,
+    blocks: Res<Blocks>,
+    block_assets: Res<Assets<Block>>,
+    mut falling: Query<(
+        Entity,
+        &mut FallingBlock,
+        &mut Transform,
+        Option<&RemoteFallingBlock>,
+    )>,
+    mut changes: EventWriter<VoxelChanged>,
+) {
+    if falling.is_empty() {
+        return;
+    }
+    let voxel_blocks = VoxelBlocks::new(&blocks, &block_assets);
+    let dt = time.delta_seconds();
+    for (e, mut block, mut transform, remote) in falling.iter_mut() {
+        block.velocity = (block.velocity + GRAVITY * dt).min(MAX_FALL_SPEED);
+        let bottom = transform.translation.y;
+        let next_bottom = bottom - block.velocity * dt;
+        let [x, z] = [
+            transform.translation.x as i32,
+            transform.translation.z as i32,
+        ];
+
+        let get = |y: i32| {
+            let pos = PointN([x, y, z]);
+            let chunk = chunks.get(*world.world.get(&voxel_chunk(pos))?).ok()?;
+            Some(chunk.data().get(pos))
+        };
+        // The first block under the falling one which it does not go through.
+        let stop = (next_bottom.floor() as i32..bottom.ceil() as i32)
+            .rev()
+            .map(|y| (y, get(y)))
+            .find(|(_, voxel)| voxel.map_or(true, |voxel| voxel_blocks.is_solid(voxel)));
+        match stop {
+            Some((ground, Some(_))) if remote.is_some() => {
+                transform.translation.y = (ground + 1) as f32;
+                block.velocity = 0.0;
+            }
+            Some((ground, Some(_))) => {
+                // Something may have been placed where it lands in the meantime.
+                let mut y = ground + 1;
+                while get(y).map_or(false, |voxel| voxel_blocks.is_solid(voxel)) {
+                    y += 1;
+                }
+                let pos = PointN([x, y, z]);
+                if set_voxel(&world, &mut chunks, pos, block.voxel, &mut changes) {
+                    commands.entity(e).despawn_recursive();
+                } else {
+                    transform.translation.y = y as f32;
+                    block.velocity = 0.0;
+                }
+            }
+            Some((_, None)) => block.velocity = 0.0,
+            None => transform.translation.y = next_bottom,
+        }
+    }
+}
+
+/// Whether the chunk of `pos` is loaded and `voxel` was set.
+fn set_voxel(
+    world: &ChunkWorld,
+    chunks: &mut Query<&mut Chunk>,
+    pos: Point3i,
+    voxel: Voxel,
+    changes: &mut EventWriter<VoxelChanged>,
+) -> bool {
+    match world
+        .world
+        .get(&voxel_chunk(pos))
+        .and_then(|e| chunks.get_mut(*e).ok())
+    {
+        Some(mut chunk) => {
+            *chunk.data_mut().get_mut(pos) = voxel;
+            changes.send(VoxelChanged { pos, voxel });
+            true
+        }
+        None => false,
+    }
+}
+
+/// Shows the falling blocks as cubes with the texture of the block, when rendering.
+fn falling_meshes(
+    mut commands: Commands,
+    blocks: Res<Blocks>,
+    block_assets: Res<Assets<Block>>,
+    asset_server: Option<Res<AssetServer>>,
+    meshes: Option<ResMut<Assets<Mesh>>>,
+    materials: Option<ResMut<Assets<StandardMaterial>>>,
+    falling: Query<(Entity, &FallingBlock), Added<FallingBlock>>,
+) {
+    let (asset_server, mut meshes, mut materials) = match (asset_server, meshes, materials) {
+        (Some(asset_server), Some(meshes), Some(materials)) => (asset_server, meshes, materials),
+        _ => return,
+    };
+    for (e, falling) in falling.iter() {
+        let block = match falling
+            .voxel
+            .block_id()
+            .and_then(|id| blocks.get_block(&block_assets, &id))
+        {
+            Some(block) => block,
+            None => continue,
+        };
+        let texture = asset_server.get_handle(&*block.texture_name);
+        let mesh = meshes.add(Mesh::from(shape::Cube { size: 1.0 }));
+        let material = materials.add(texture.into());
+        commands.entity(e).with_children(|builder| {
+            builder.spawn_bundle(PbrBundle {
+                mesh,
+                material,
+                transform: Transform::from_xyz(0.5, 0.5, 0.5),
+                ..Default::default()
+            });
+        });
+    }
+}
@@ -1,272 +1,514 @@
-use std::cmp::max;
+use std::cmp::{max, Ordering};
+use std::collections::HashMap;
+use std::fs;
+use std::path::Path;
 
+use bevy::app::ManualEventReader;
 use bevy::prelude::*;
+use bevy::tasks::{AsyncComputeTaskPool, Task};
 use building_blocks::core::{Extent2i, Extent3i, Point3i, PointN};
 use building_blocks::prelude::{Array2x1, Array3x1, Get, GetMut};
 use noise::{
     Fbm, MultiFractal, NoiseFn, Point2, Point3, ScaleBias, Seedable, SuperSimplex,
 };
+use futures_lite::future;
+use serde_derive::Deserialize;
 
 use crate::{
-    App, AppState, Commands, Entity, EventReader, NonSendMut, Plugin, Query, Res, ResMut,
-    SystemSet, Transform, Vec3,
+    App, Commands, Entity, NonSendMut, Plugin, Query, Res, ResMut, SystemSet, Transform, Vec3,
+};
+use crate::chunk::{
+    chunk_extent, voxel_chunk, Chunk, ChunkEvent, ChunkWorld, Relative, Voxel, CHUNK_SIZE,
+};
+pub use column_cache::{ColumnCache, COLUMN_CACHE_HITS, COLUMN_CACHE_MISSES};
+pub use erosion::{Erosion, ErosionSettings};
+pub use facet::{
+    FacetAppExt, FacetContext, FacetGraphError, FacetId, FacetProvider, FacetRegistry, Facets,
+    GeneratorResources,
+};
+pub use heightmap::{Heightmap, HeightmapSettings};
+use hydrology::{LakeFacet, RiverFacet};
+pub use hydrology::WaterLevelFacet;
+pub use noise_graph::{
+    FractalDesc, NoiseGraph, NoiseGraphDesc, NoiseGraphError, NoiseGraphs, NoiseNode,
 };
-use crate::chunk::{Chunk, ChunkEvent, ChunkWorld, Voxel};
+
+mod column_cache;
+mod erosion;
+mod facet;
+mod heightmap;
+mod hydrology;
+mod noise_graph;
 
 pub struct ChunkGeneratorPlugin;
 
-type Seed = i64;
+pub type Seed = i64;
+
+const DEFAULT_SEED: Seed = 124235;
 
 impl Plugin for ChunkGeneratorPlugin {
     fn build(&self, app: &mut App) {
-        app
-            .add_system_set(
-                SystemSet::new()
-                    .label("facets")
-                    .with_system(provide_sealevel_facet)
-                    .with_system(provide_noise_elevation_facet)
-                    .with_system(provide_surface_facet)
-                    .with_system(provide_density_facet)
-                    .with_system(provide_roughness_facet)
-                    .with_system(provide_density_noise),
+        app.init_resource::<WorldPreset>()
+            .init_resource::<GeneratorResources>()
+            .init_resource::<FacetRegistry>()
+            .add_facet_provider(FacetProvider::new(provide_sealevel_facet).per_column())
+            .add_facet_provider(FacetProvider::new(hydrology::provide_river_facet).per_column())
+            .add_facet_provider(
+                FacetProvider::new(hydrology::provide_lake_facet)
+                    .per_column()
+                    .after::<SeaLevel>(),
+            )
+            .add_facet_provider(
+                FacetProvider::new(provide_noise_elevation_facet)
+                    .per_column()
+                    .after::<SeaLevel>()
+                    .after::<RiverFacet>()
+                    .after::<LakeFacet>(),
+            )
+            .add_facet_provider(
+                FacetProvider::new(hydrology::provide_water_level_facet)
+                    .per_column()
+                    .after::<SeaLevel>()
+                    .after::<ElevationFacet>()
+                    .after::<RiverFacet>()
+                    .after::<LakeFacet>(),
             )
+            .add_facet_provider(
+                FacetProvider::new(provide_roughness_facet)
+                    .per_column()
+                    .after::<SeaLevel>()
+                    .after::<ElevationFacet>()
+                    .after::<RiverFacet>()
+                    .after::<LakeFacet>(),
+            )
+            .add_facet_provider(
+                FacetProvider::new(provide_density_facet)
+                    .after::<ElevationFacet>()
+                    .after::<SurfaceRoughnessFacet>(),
+            )
+            .add_facet_provider(
+                FacetProvider::new(provide_surface_facet)
+                    .after::<ElevationFacet>()
+                    .after::<DensityFacet>(),
+            )
+            .add_startup_system(share_preset)
+            .add_startup_system(share_erosion)
+            .add_startup_system(heightmap::load_heightmap)
+            .add_startup_system(noise_graph::load_noise_graphs)
+            .add_startup_system(column_cache::setup_diagnostics)
+            .add_startup_system_to_stage(StartupStage::PostStartup, facet::schedule_facets)
+            .init_resource::<GeneratorSettings>()
             .add_system_set(
                 SystemSet::new()
-                    .label("rasterizers")
-                    .after("facets")
-                    .with_system(generate_chunk_system),
+                    .label("generation")
+                    .with_system(generate_chunk)
+                    .with_system(spawn_generation_tasks)
+                    .with_system(generation_done)
+                    .with_system(column_cache::diagnostics),
             );
+
+        {
+            let mut registry = app.world.get_resource_mut::<FacetRegistry>().unwrap();
+            registry.require::<SurfaceFacet>();
+            registry.require::<DensityFacet>();
+            registry.require::<WaterLevelFacet>();
+        }
+        let mut resources = app.world.get_resource_mut::<GeneratorResources>().unwrap();
+        if !resources.contains::<Erosion>() {
+            resources.insert(Erosion::default());
+        }
     }
 }
 
-#[derive(Component)]
-struct Facet<T>(T);
-
-struct SeaLevel(i32);
+/// Settings chosen when creating a world.
+#[derive(Debug, Clone, Deserialize)]
+pub struct WorldPreset {
+    #[serde(default = "default_seed")]
+    pub seed: Seed,
+    /// Elevation is read from this image instead of noise where it covers the world.
+    #[serde(default)]
+    pub heightmap: Option<HeightmapSettings>,
+    /// Paths of `.noise.ron` graphs by name, replacing the built-in noise of the facets reading them.
+    ///
+    /// - `elevation` replaces the elevation noise, read at `[x, 0, z]` and mostly between -1 and 1.
+    /// - `roughness` replaces the surface roughness noise, read at `[x, 0, z]`.
+    /// - `density` is added to the density, scaled by the surface roughness.
+    #[serde(default)]
+    pub noise: HashMap<String, String>,
+}
 
-struct ElevationFacet(Facet2D<i32>);
+impl Default for WorldPreset {
+    fn default() -> Self {
+        WorldPreset {
+            seed: DEFAULT_SEED,
+            heightmap: None,
+            noise: HashMap::new(),
+        }
+    }
+}
 
-struct SurfaceRoughnessFacet(Facet2D<f32>);
+fn default_seed() -> Seed {
+    DEFAULT_SEED
+}
 
-struct SurfaceFacet(Facet3D<bool>);
+impl WorldPreset {
+    /// Loads a preset file, the heightmap path is relative to it.
+    pub fn load<P: AsRef<Path>>(path: P) -> anyhow::Result<Self> {
+        let path = path.as_ref();
+        let str = fs::read_to_string(path)?;
+        let mut preset: WorldPreset = ron::from_str(&str)?;
+        if let (Some(heightmap), Some(dir)) = (&mut preset.heightmap, path.parent()) {
+            heightmap.path = dir.join(&heightmap.path).to_string_lossy().into_owned();
+        }
+        Ok(preset)
+    }
+}
 
-struct DensityFacet(Facet3D<f32>);
+/// Makes the preset readable by facet providers.
+fn share_preset(preset: Res<WorldPreset>, mut resources: ResMut<GeneratorResources>) {
+    resources.insert(preset.clone());
+}
 
-fn provide_sealevel_facet(
-    mut commands: Commands,
-    query: Query<Entity, (With<GeneratingArea>, Without<Facet<SeaLevel>>)>,
-) {
-    for e in query.iter() {
-        commands.entity(e).insert(Facet(SeaLevel(32)));
+/// Erosion inserted as a resource replaces the one of the facet providers.
+fn share_erosion(erosion: Option<Res<Erosion>>, mut resources: ResMut<GeneratorResources>) {
+    if let Some(erosion) = erosion {
+        resources.insert(Erosion::new(erosion.settings.clone()));
     }
 }
 
-fn provide_flat_elevation_facet(
-    mut commands: Commands,
-    query: Query<(Entity, &GeneratingArea), Without<Facet<ElevationFacet>>>,
-) {
-    for (e, area) in query.iter() {
-        let mut facet = Facet2D::new(area.0);
-        for val in facet.data.channels_mut().store_mut().iter_mut() {
-            *val = 40;
-        }
-        commands.entity(e).insert(Facet(ElevationFacet(facet)));
+pub struct SeaLevel(pub i32);
+
+pub struct ElevationFacet(pub Facet2D<i32>);
+
+pub struct SurfaceRoughnessFacet(pub Facet2D<f32>);
+
+pub struct SurfaceFacet(pub Facet3D<bool>);
+
+pub struct DensityFacet(pub Facet3D<f32>);
+
+fn provide_sealevel_facet(_: &FacetContext) -> SeaLevel {
+    SeaLevel(32)
+}
+
+fn provide_flat_elevation_facet(context: &FacetContext) -> ElevationFacet {
+    let mut facet = Facet2D::new(context.area());
+    for val in facet.data.channels_mut().store_mut().iter_mut() {
+        *val = 40;
     }
+    ElevationFacet(facet)
 }
 
-fn provide_noise_elevation_facet(
-    mut commands: Commands,
-    query: Query<(Entity, &GeneratingArea, &Facet<SeaLevel>), Without<Facet<ElevationFacet>>>,
-) {
-    for (e, area, sea_level) in query.iter() {
-        let sea_level = sea_level.0.0 as f64;
-        let fbm = Fbm::new().set_octaves(8).set_seed(124235);
-        let noise = SubSampleNoise::new(&fbm)
-            .set_scale([0.004, 0.004, 1.0])
-            .set_sample_rate(4);
+/// Seed of the world being generated, noise functions offset it to differ from each other.
+fn world_seed(context: &FacetContext) -> u32 {
+    context
+        .resource::<WorldPreset>()
+        .map_or(DEFAULT_SEED, |preset| preset.seed) as u32
+}
+
+fn elevation_noise(seed: u32) -> Fbm {
+    Fbm::new().set_octaves(8).set_seed(seed)
+}
 
-        let mut facet = Facet2D::new(area.0);
-        for pos in facet.data.extent().iter_points() {
-            let mut val = facet.data.get_mut(pos);
-            let mut x = noise.get([pos.x() as f64 + 0.1, pos.y() as f64 + 0.1]);
-            x = sea_level + sea_level * ((x * 2.11 + 1.0) / 2.0);
-            *val = x as i32;
+/// Height of the terrain at a column before rivers and lakes are carved into it.
+fn noise_elevation(noise: &dyn NoiseFn<Point2<f64>>, sea_level: f64, x: f64, z: f64) -> f64 {
+    let value = noise.get([x + 0.1, z + 0.1]);
+    sea_level + sea_level * ((value * 2.11 + 1.0) / 2.0)
+}
+
+/// Elevation of the noise, or of the preset's `elevation` graph, eroded but before rivers and
+/// lakes are carved into it.
+fn base_elevation(context: &FacetContext, columns: Extent2i) -> Array2x1<f64> {
+    let sea_level = context.facet::<SeaLevel>().0 as f64;
+    let seed = world_seed(context);
+    let fbm = elevation_noise(seed);
+    let built_in = SubSampleNoise::new(&fbm)
+        .set_scale([0.004, 0.004, 1.0])
+        .set_sample_rate(4);
+    let noise: &dyn NoiseFn<Point2<f64>> = match context.noise_graph("elevation") {
+        Some(graph) => graph,
+        None => &built_in,
+    };
+
+    let regions = match context.resource::<Erosion>() {
+        Some(erosion) if erosion.settings.enabled => {
+            erosion.regions(columns, noise, sea_level, seed)
         }
-        commands.entity(e).insert(Facet(ElevationFacet(facet)));
+        _ => Vec::new(),
+    };
+    let mut heights = Array2x1::fill(columns, 0.0);
+    for pos in columns.iter_points() {
+        let (x, z) = (pos.x() as f64, pos.y() as f64);
+        let mut height = noise_elevation(noise, sea_level, x, z);
+        for region in regions.iter() {
+            height += region.weighted_delta(x, z);
+        }
+        *heights.get_mut(pos) = height;
     }
+    heights
 }
 
-fn provide_roughness_facet(
-    mut commands: Commands,
-    query: Query<
-        (
-            Entity,
-            &GeneratingArea,
-            &Facet<SeaLevel>,
-            &Facet<ElevationFacet>,
-        ),
-        Without<Facet<SurfaceRoughnessFacet>>,
-    >,
-) {
-    for (e, area, sea_level, elevation) in query.iter() {
-        let fbm = Fbm::new().set_octaves(8).set_seed(124235 + 92658);
-        let noise = ScaleBias::new(&fbm).set_scale(0.0004); // TODO add sample rate :/
-
-        let mut facet = SurfaceRoughnessFacet(Facet2D::new(area.0));
-        let sea_level = sea_level.0.0;
-        let elevation = &elevation.0.0;
-        for pos in facet.0.data.extent().iter_points() {
-            let value = facet.0.data.get_mut(pos);
-            let height = elevation.data.get(pos) - sea_level;
-            *value = (0.25
-                + height as f64 * 0.007
-                + noise.get([pos.x() as f64 / 500.0, pos.y() as f64 / 500.0]) * 1.5)
-                as f32;
+fn provide_noise_elevation_facet(context: &FacetContext) -> ElevationFacet {
+    let sea_level = context.facet::<SeaLevel>().0 as f64;
+    let rivers = &context.facet::<RiverFacet>().0;
+    let lakes = &context.facet::<LakeFacet>().0;
+
+    let mut facet = Facet2D::new(context.area());
+    let base = base_elevation(context, *facet.data.extent());
+    for pos in facet.data.extent().iter_points() {
+        let mut val = facet.data.get_mut(pos);
+        let (x, z) = (pos.x() as f64, pos.y() as f64);
+        let mut height = base.get(pos);
+        for lake in lakes.iter() {
+            height = lake.carve(height, x, z);
         }
-        commands.entity(e).insert(Facet(facet));
+        height = rivers.data.get(pos).carve(height, sea_level);
+        *val = height as i32;
     }
+    ElevationFacet(facet)
 }
 
-fn provide_surface_facet(
-    mut commands: Commands,
-    query: Query<(Entity, &GeneratingArea, &Facet<ElevationFacet>), Without<Facet<SurfaceFacet>>>,
-) {
-    for (e, area, elevation) in query.iter() {
-        let elevation = &elevation.0;
-        let mut surface = SurfaceFacet(Facet3D::new(area.0));
-        for pos in elevation.0.data.extent().iter_points() {
-            let height = elevation.0.data.get(pos);
-            let pos = PointN([pos.x(), height, pos.y()]);
-            if surface.0.data.contains(pos) {
-                let value = surface.0.data.get_mut(pos);
-                *value = true;
-            }
-        }
-        commands.entity(e).insert(Facet(surface));
+fn provide_roughness_facet(context: &FacetContext) -> SurfaceRoughnessFacet {
+    let fbm = Fbm::new()
+        .set_octaves(8)
+        .set_seed(world_seed(context).wrapping_add(92658));
+    let built_in = ScaleBias::new(&fbm).set_scale(0.0004); // TODO add sample rate :/
+    let graph = context.noise_graph("roughness");
+    let noise = |x: f64, z: f64| match graph {
+        Some(graph) => NoiseFn::<Point2<f64>>::get(graph, [x, z]),
+        None => built_in.get([x / 500.0, z / 500.0]),
+    };
+
+    let mut facet = SurfaceRoughnessFacet(Facet2D::new(context.area()));
+    let sea_level = context.facet::<SeaLevel>().0;
+    let elevation = &context.facet::<ElevationFacet>().0;
+    let rivers = &context.facet::<RiverFacet>().0;
+    let lakes = &context.facet::<LakeFacet>().0;
+    for pos in facet.0.data.extent().iter_points() {
+        let value = facet.0.data.get_mut(pos);
+        let height = elevation.data.get(pos) - sea_level;
+        let wetness =
+            hydrology::wetness(rivers.data.get(pos), lakes, pos.x() as f64, pos.y() as f64);
+        *value = (0.25 + height as f64 * 0.007 + noise(pos.x() as f64, pos.y() as f64) * 1.5)
+            as f32
+            * (1.0 - wetness);
     }
+    facet
 }
 
-fn provide_density_facet(
-    mut commands: Commands,
-    query: Query<(Entity, &GeneratingArea, &Facet<ElevationFacet>), Without<Facet<DensityFacet>>>,
-) {
-    for (e, area, elevation) in query.iter() {
-        let elevation = &elevation.0;
-        let mut density = DensityFacet(Facet3D::new(area.0));
-        for pos in elevation.0.data.extent().iter_points() {
-            let height = elevation.0.data.get(pos);
-            let min_y = density.0.data.extent().minimum.y();
-            let max_y = min_y + density.0.data.extent().shape.y();
-            for y in min_y..max_y {
-                let pos = PointN([pos.x(), y, pos.y()]);
-                let value = density.0.data.get_mut(pos);
-                *value = (height - y) as f32;
-            }
+/// Density falls off with the height above the elevation and is disturbed by roughness noise.
+fn provide_density_facet(context: &FacetContext) -> DensityFacet {
+    let elevation = &context.facet::<ElevationFacet>().0;
+    let roughness = &context.facet::<SurfaceRoughnessFacet>().0;
+    let mut density = DensityFacet(Facet3D::new(context.area()));
+    for pos in elevation.data.extent().iter_points() {
+        let height = elevation.data.get(pos);
+        let min_y = density.0.data.extent().minimum.y();
+        let max_y = min_y + density.0.data.extent().shape.y();
+        for y in min_y..max_y {
+            let pos = PointN([pos.x(), y, pos.y()]);
+            let value = density.0.data.get_mut(pos);
+            *value = (height - y) as f32;
         }
-        commands.entity(e).insert(Facet(density));
     }
-}
 
-fn provide_density_noise(
-    mut query: Query<
-        (
-            &Facet<SurfaceRoughnessFacet>,
-            &mut Facet<DensityFacet>,
-            &mut Facet<SurfaceFacet>,
-        ),
-        With<GeneratingArea>,
-    >,
-) {
-    for (roughness, mut density, mut surface) in query.iter_mut() {
-        let density = &mut density.0.0;
-        let surface = &mut surface.0.0;
-        let roughness = &roughness.0.0;
-        let fbm = Fbm::new()
-            .set_octaves(8)
-            .set_seed(124235)
-            .set_persistence(1.0);
-        let large_noise = SubSampleNoise::new(&fbm)
-            .set_scale([0.015, 0.02, 0.015])
-            .set_sample_rate(4);
-        let small_noise = SubSampleNoise::new(&fbm)
-            .set_scale([0.005, 0.007, 0.005])
-            .set_sample_rate(4);
-        for pos in density.data.extent().iter_points() {
-            let value = density.data.get_mut(pos);
+    let data = &mut density.0.data;
+    if let Some(graph) = context.noise_graph("density") {
+        let mut noise = Array3x1::fill(*data.extent(), 0.0);
+        graph.fill(&mut noise);
+        for pos in data.extent().iter_points() {
+            let value = data.get_mut(pos);
             let intensity = f32::max(0.0, roughness.data.get(pos.xy()));
-            let small_intensity = f32::min(intensity, (1.0 + intensity) / 2.0);
-            let large_intensity = intensity - small_intensity;
-
-            *value = *value
-                + small_noise.get([pos.x() as f64, pos.y() as f64, pos.z() as f64]) as f32
-                * intensity
-                * 20.0
-                + large_noise.get([pos.x() as f64, pos.y() as f64, pos.z() as f64]) as f32
-                * large_intensity
-                * 60.0;
+            *value += noise.get(pos) as f32 * intensity;
         }
+        return density;
+    }
 
-        for pos in surface.data.extent().iter_points() {
-            if density.data.contains(pos) && density.data.contains(pos + PointN([0, 1, 0])) {
-                let value = surface.data.get_mut(pos);
-                *value =
-                    density.data.get(pos) > 0.0 && density.data.get(pos + PointN([0, 1, 0])) <= 0.0;
-            }
+    let fbm = Fbm::new()
+        .set_octaves(8)
+        .set_seed(world_seed(context))
+        .set_persistence(1.0);
+    let large_noise = SubSampleNoise::new(&fbm)
+        .set_scale([0.015, 0.02, 0.015])
+        .set_sample_rate(4);
+    let small_noise = SubSampleNoise::new(&fbm)
+        .set_scale([0.005, 0.007, 0.005])
+        .set_sample_rate(4);
+    let mut large = Array3x1::fill(*data.extent(), 0.0);
+    let mut small = Array3x1::fill(*data.extent(), 0.0);
+    large_noise.fill(&mut large);
+    small_noise.fill(&mut small);
+    for pos in data.extent().iter_points() {
+        let value = data.get_mut(pos);
+        let intensity = f32::max(0.0, roughness.data.get(pos.xy()));
+        let small_intensity = f32::min(intensity, (1.0 + intensity) / 2.0);
+        let large_intensity = intensity - small_intensity;
+
+        *value = *value
+            + small.get(pos) as f32 * intensity * 20.0
+            + large.get(pos) as f32 * large_intensity * 60.0;
+    }
+    density
+}
+
+/// Topmost solid voxels, taken from the density where the voxel above is known
+/// and from the elevation on the top layer.
+fn provide_surface_facet(context: &FacetContext) -> SurfaceFacet {
+    let elevation = &context.facet::<ElevationFacet>().0;
+    let density = &context.facet::<DensityFacet>().0;
+    let mut surface = SurfaceFacet(Facet3D::new(context.area()));
+    for pos in elevation.data.extent().iter_points() {
+        let height = elevation.data.get(pos);
+        let pos = PointN([pos.x(), height, pos.y()]);
+        if surface.0.data.contains(pos) {
+            let value = surface.0.data.get_mut(pos);
+            *value = true;
+        }
+    }
+
+    let surface_data = &mut surface.0.data;
+    for pos in surface_data.extent().iter_points() {
+        if density.data.contains(pos) && density.data.contains(pos + PointN([0, 1, 0])) {
+            let value = surface_data.get_mut(pos);
+            *value =
+                density.data.get(pos) > 0.0 && density.data.get(pos + PointN([0, 1, 0])) <= 0.0;
         }
     }
+    surface
 }
 
-fn generate_chunk_system(
+/// Starts generating the waiting areas closest to a viewer, up to `max_tasks` at a time.
+fn spawn_generation_tasks(
     mut commands: Commands,
-    mut query: Query<(
-        Entity,
-        &mut Chunk,
-        &Facet<SurfaceFacet>,
-        &Facet<DensityFacet>,
-        &Facet<SeaLevel>,
-    ), With<GeneratingArea>>,
+    pool: Res<AsyncComputeTaskPool>,
+    settings: Res<GeneratorSettings>,
+    registry: Res<FacetRegistry>,
+    resources: Res<GeneratorResources>,
+    running: Query<(), With<Task<Chunk>>>,
+    waiting: Query<(Entity, &GeneratingArea), Without<Task<Chunk>>>,
+    viewers: Query<&Transform, With<Relative>>,
 ) {
+    let free = settings.max_tasks.saturating_sub(running.iter().count());
+    if free == 0 {
+        return;
+    }
+    let viewers: Vec<Vec3> = viewers.iter().map(|t| t.translation).collect();
+    let mut waiting: Vec<_> = waiting
+        .iter()
+        .map(|(e, area)| (distance_to_viewers(&area.0, &viewers), e, area.0))
+        .collect();
+    waiting.sort_by(|a, b| a.0.partial_cmp(&b.0).unwrap_or(Ordering::Equal));
+
+    for (_, e, area) in waiting.into_iter().take(free) {
+        let registry = registry.clone();
+        let resources = resources.clone();
+        let task = pool.spawn(async move { generate_area(&registry, &resources, area).1 });
+        commands.entity(e).insert(task);
+    }
+}
+
+fn distance_to_viewers(area: &Extent3i, viewers: &[Vec3]) -> f32 {
+    let [x, y, z] = area.minimum.0;
+    let [w, h, d] = area.shape.0;
+    let center = Vec3::new(
+        x as f32 + w as f32 / 2.0,
+        y as f32 + h as f32 / 2.0,
+        z as f32 + d as f32 / 2.0,
+    );
+    viewers
+        .iter()
+        .map(|viewer| viewer.distance_squared(center))
+        .fold(f32::MAX, f32::min)
+}
+
+fn generation_done(mut commands: Commands, mut query: Query<(Entity, &mut Task<Chunk>)>) {
+    for (e, mut task) in query.iter_mut() {
+        if let Some(chunk) = future::block_on(future::poll_once(&mut *task)) {
+            commands
+                .entity(e)
+                .insert(chunk)
+                .remove::<Task<Chunk>>()
+                .remove::<GeneratingArea>();
+        }
+    }
+}
+
+/// Computes the facets of `area` and rasterizes them, as the generation tasks do.
+pub fn generate_area(
+    registry: &FacetRegistry,
+    resources: &GeneratorResources,
+    area: Extent3i,
+) -> (Facets, Chunk) {
+    let facets = registry.provide(area, resources);
+    let mut chunk = Chunk {
+        pos: voxel_chunk(area.minimum),
+        data: Array3x1::fill(area, Voxel::default()),
+    };
+    rasterize(&facets, &mut chunk);
+    (facets, chunk)
+}
+
+fn rasterize(facets: &Facets, chunk: &mut Chunk) {
     const DIRT: i32 = 1;
     const STONE: i32 = 2;
     const WATER: i32 = 4;
-    for (e, mut chunk, surface, solidity, sealevel) in query.iter_mut() {
-        let solidity = &solidity.0;
-        let surface = &surface.0;
-        let sealevel = &sealevel.0;
-        let sea_level = sealevel.0;
-
-        for pos in chunk.data.extent().iter_points() {
-            let value = chunk.data.get_mut(pos);
-            let density = solidity.0.data.get(pos);
-            let pos_y = pos.y() + max(0, density as i32);
-
-            if pos.y() < sea_level && pos_y > sea_level {
-                *value = Voxel(WATER as u8);
-            } else if density > 0.0 && surface.0.data.get(pos) {
+    let surface = facets.get::<SurfaceFacet>().unwrap();
+    let solidity = facets.get::<DensityFacet>().unwrap();
+    let water_level = facets.get::<WaterLevelFacet>().unwrap();
+
+    for pos in chunk.data.extent().iter_points() {
+        let value = chunk.data.get_mut(pos);
+        let density = solidity.0.data.get(pos);
+        let pos_y = pos.y() + max(0, density as i32);
+        let water_level = water_level.0.data.get(pos.xz());
+
+        if pos.y() < water_level && pos_y > water_level {
+            *value = Voxel(WATER as u8);
+        } else if density > 0.0 && surface.0.data.get(pos) {
+            *value = Voxel(DIRT as u8);
+        } else if density > 0.0 {
+            if density > 32.0 {
+                *value = Voxel(STONE as u8);
+            } else {
                 *value = Voxel(DIRT as u8);
-            } else if density > 0.0 {
-                if density > 32.0 {
-                    *value = Voxel(STONE as u8);
-                } else {
-                    *value = Voxel(DIRT as u8);
-                }
-            } else if pos_y <= sea_level {
-                *value = Voxel(WATER as u8);
             }
+        } else if pos_y <= water_level {
+            *value = Voxel(WATER as u8);
         }
-        commands.entity(e).remove::<GeneratingArea>();
     }
 }
 
+/// Voxels waiting to be generated, replaced by a `Chunk` once done.
 #[derive(Component)]
 pub struct GeneratingArea(Extent3i);
 
+impl GeneratingArea {
+    pub fn chunk(pos: Point3i) -> Self {
+        GeneratingArea(chunk_extent(pos))
+    }
+}
+
+pub struct GeneratorSettings {
+    /// Maximum number of areas generated at the same time.
+    pub max_tasks: usize,
+    /// Maximum number of per column facets kept in the column cache.
+    pub column_cache_capacity: usize,
+}
+
+impl Default for GeneratorSettings {
+    fn default() -> Self {
+        GeneratorSettings {
+            max_tasks: 4,
+            column_cache_capacity: ColumnCache::DEFAULT_CAPACITY,
+        }
+    }
+}
+
 pub struct Facet2D<T> {
-    data: Array2x1<T>,
+    pub data: Array2x1<T>,
 }
 
 impl<T: Default + Clone> Facet2D<T> {
-    fn new(region: Extent3i) -> Self {
+    pub fn new(region: Extent3i) -> Self {
         let pos = region.minimum.xz();
         let shape = region.shape.xz();
         let area = Extent2i::from_min_and_shape(pos, shape);
@@ -278,11 +520,11 @@ impl<T: Default + Clone> Facet2D<T> {
 
 //
 pub struct Facet3D<T> {
-    data: Array3x1<T>,
+    pub data: Array3x1<T>,
 }
 
 impl<T: Default + Clone> Facet3D<T> {
-    fn new(region: Extent3i) -> Self {
+    pub fn new(region: Extent3i) -> Self {
         Facet3D {
             data: Array3x1::fill(region, T::default()),
         }
@@ -291,36 +533,35 @@ impl<T: Default + Clone> Facet3D<T> {
 
 //// TODO OLD CODE BELOW
 
+/// Claims the chunks `ChunkPlugin` asks for, apps without it only generate the areas they spawn.
 pub fn generate_chunk(
     mut commands: Commands,
-    mut reader: EventReader<ChunkEvent>,
-    world: Res<ChunkWorld>,
+    events: Option<Res<Events<ChunkEvent>>>,
+    mut reader: Local<ManualEventReader<ChunkEvent>>,
+    world: Option<ResMut<ChunkWorld>>,
 ) {
-    for event in reader.iter() {
+    let (events, mut world) = match (events, world) {
+        (Some(events), Some(world)) => (events, world),
+        _ => return,
+    };
+    for event in reader.iter(&events) {
         let event: &ChunkEvent = event;
         match event {
             ChunkEvent::Generate(pos) => {
                 let pos = pos.0;
                 if !world.world.contains_key(&pos) {
                     let transform = Transform::from_translation(Vec3::from([
-                        pos.x() as f32 * 32.0,
-                        pos.y() as f32 * 32.0,
-                        pos.z() as f32 * 32.0,
+                        (pos.x() * CHUNK_SIZE) as f32,
+                        (pos.y() * CHUNK_SIZE) as f32,
+                        (pos.z() * CHUNK_SIZE) as f32,
                     ]));
-                    let extent =
-                        Extent3i::from_min_and_shape(pos * PointN([32; 3]), PointN([32; 3]));
-
-                    let mut chunk = Chunk {
-                        data: Array3x1::fill(extent, Voxel::default()),
-                        pos,
-                    };
-                    chunk.pos = pos;
-
-                    commands
+                    // The chunk is only inserted once generated, claim its position right away.
+                    let e = commands
                         .spawn()
-                        .insert(GeneratingArea(extent))
+                        .insert(GeneratingArea::chunk(pos))
                         .insert(transform)
-                        .insert(chunk);
+                        .id();
+                    world.world.insert(pos, e);
                 }
             }
             ChunkEvent::Update(_) => {}
@@ -360,6 +601,106 @@ impl<'a, T: 'a> SubSampleNoise<'a, T> {
     }
 }
 
+impl<'a> SubSampleNoise<'a, Point2<f64>> {
+    /// Fills `array` with the same values `get` returns for each of its points,
+    /// sampling the source only once per lattice point.
+    pub fn fill(&self, array: &mut Array2x1<f64>) {
+        let extent = *array.extent();
+        let rate = self.sample_rate;
+        let [min_x, min_y] = extent.minimum.0;
+        let [max_x, max_y] = extent.max().0;
+        let (lattice_x, width) = lattice(min_x, max_x, rate);
+        let (lattice_y, height) = lattice(min_y, max_y, rate);
+
+        let mut samples = Vec::with_capacity(width * height);
+        for y in 0..height as i32 {
+            for x in 0..width as i32 {
+                let x = ((lattice_x + x) * rate) as f64;
+                let y = ((lattice_y + y) * rate) as f64;
+                samples.push(self.source.get([x * self.scale[0], y * self.scale[1]]));
+            }
+        }
+
+        let sample = |x: usize, y: usize| samples[y * width + x];
+        for pos in extent.iter_points() {
+            let (x, tx) = lattice_cell(pos.x(), lattice_x, rate);
+            let (y, ty) = lattice_cell(pos.y(), lattice_y, rate);
+            *array.get_mut(pos) = math::bi_lerp(
+                sample(x, y),
+                sample(x + 1, y),
+                sample(x, y + 1),
+                sample(x + 1, y + 1),
+                tx,
+                ty,
+            );
+        }
+    }
+}
+
+impl<'a> SubSampleNoise<'a, Point3<f64>> {
+    /// Fills `array` with the same values `get` returns for each of its points,
+    /// sampling the source only once per lattice point.
+    pub fn fill(&self, array: &mut Array3x1<f64>) {
+        let extent = *array.extent();
+        let rate = self.sample_rate;
+        let [min_x, min_y, min_z] = extent.minimum.0;
+        let [max_x, max_y, max_z] = extent.max().0;
+        let (lattice_x, width) = lattice(min_x, max_x, rate);
+        let (lattice_y, height) = lattice(min_y, max_y, rate);
+        let (lattice_z, depth) = lattice(min_z, max_z, rate);
+
+        let mut samples = Vec::with_capacity(width * height * depth);
+        for z in 0..depth as i32 {
+            for y in 0..height as i32 {
+                for x in 0..width as i32 {
+                    let x = ((lattice_x + x) * rate) as f64;
+                    let y = ((lattice_y + y) * rate) as f64;
+                    let z = ((lattice_z + z) * rate) as f64;
+                    samples.push(self.source.get([
+                        x * self.scale[0],
+                        y * self.scale[1],
+                        z * self.scale[2],
+                    ]));
+                }
+            }
+        }
+
+        let sample = |x: usize, y: usize, z: usize| samples[(z * height + y) * width + x];
+        for pos in extent.iter_points() {
+            let (x, tx) = lattice_cell(pos.x(), lattice_x, rate);
+            let (y, ty) = lattice_cell(pos.y(), lattice_y, rate);
+            let (z, tz) = lattice_cell(pos.z(), lattice_z, rate);
+            *array.get_mut(pos) = math::tri_lerp(
+                sample(x, y, z),
+                sample(x + 1, y, z),
+                sample(x, y + 1, z),
+                sample(x + 1, y + 1, z),
+                sample(x, y, z + 1),
+                sample(x + 1, y, z + 1),
+                sample(x, y + 1, z + 1),
+                sample(x + 1, y + 1, z + 1),
+                tx,
+                ty,
+                tz,
+            );
+        }
+    }
+}
+
+/// First lattice point at or below `min` and the number of lattice points up to past `max`.
+fn lattice(min: i32, max: i32, rate: i32) -> (i32, usize) {
+    let first = min.div_euclid(rate);
+    let last = max.div_euclid(rate) + 1;
+    (first, (last - first + 1) as usize)
+}
+
+/// Lattice cell of `value` relative to the `first` lattice point, and the position within it.
+fn lattice_cell(value: i32, first: i32, rate: i32) -> (usize, f64) {
+    let cell = (value.div_euclid(rate) - first) as usize;
+    let t = value.rem_euclid(rate) as f64 / rate as f64;
+    (cell, t)
+}
+
 impl<'a> NoiseFn<Point2<f64>> for SubSampleNoise<'a, Point2<f64>> {
     fn get(&self, point: Point2<f64>) -> f64 {
         let [x, y] = point;
@@ -477,3 +818,51 @@ mod math {
         lerp(y0, y1, tz)
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn sub_sample_fill_matches_get_2d() {
+        let fbm = Fbm::new().set_octaves(4);
+        let noise = SubSampleNoise::new(&fbm)
+            .set_scale([0.03, 0.05, 1.0])
+            .set_sample_rate(4);
+        // Neither the minimum nor the maximum is on the lattice.
+        let extent = Extent2i::from_min_and_shape(PointN([-7, 3]), PointN([13, 10]));
+        let mut array = Array2x1::fill(extent, 0.0);
+        noise.fill(&mut array);
+        for pos in extent.iter_points() {
+            let expected = noise.get([pos.x() as f64, pos.y() as f64]);
+            assert!(
+                (array.get(pos) - expected).abs() < 1e-9,
+                "{:?} filled with {} instead of {}",
+                pos,
+                array.get(pos),
+                expected
+            );
+        }
+    }
+
+    #[test]
+    fn sub_sample_fill_matches_get_3d() {
+        let fbm = Fbm::new().set_octaves(4);
+        let noise = SubSampleNoise::new(&fbm)
+            .set_scale([0.03, 0.05, 0.04])
+            .set_sample_rate(4);
+        let extent = Extent3i::from_min_and_shape(PointN([-5, 3, -9]), PointN([13, 9, 11]));
+        let mut array = Array3x1::fill(extent, 0.0);
+        noise.fill(&mut array);
+        for pos in extent.iter_points() {
+            let expected = noise.get([pos.x() as f64, pos.y() as f64, pos.z() as f64]);
+            assert!(
+                (array.get(pos) - expected).abs() < 1e-9,
+                "{:?} filled with {} instead of {}",
+                pos,
+                array.get(pos),
+                expected
+            );
+        }
+    }
+}
@@ -0,0 +1,219 @@
+use std::any::Any;
+use std::collections::{BTreeMap, HashMap};
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{Arc, Mutex};
+
+use bevy::diagnostic::{Diagnostic, DiagnosticId, Diagnostics};
+use bevy::prelude::*;
+use building_blocks::core::Extent3i;
+
+use super::{FacetId, FacetRegistry, Seed};
+
+pub const COLUMN_CACHE_HITS: DiagnosticId =
+    DiagnosticId::from_u128(0x6d1c_02f4_64c8_4c61_9b0e_3f4b_a1d5_e701);
+pub const COLUMN_CACHE_MISSES: DiagnosticId =
+    DiagnosticId::from_u128(0x6d1c_02f4_64c8_4c61_9b0e_3f4b_a1d5_e702);
+
+pub(super) type FacetValue = Arc<dyn Any + Send + Sync>;
+
+#[derive(Clone, Copy, PartialEq, Eq, Hash)]
+struct ColumnKey {
+    facet: FacetId,
+    /// Minimum and shape of the area on the x and z axes.
+    column: [i32; 4],
+    seed: Seed,
+}
+
+struct Entry {
+    value: FacetValue,
+    last_used: u64,
+}
+
+/// Least recently used cache of facets which only depend on the columns of an area,
+/// shared by every chunk stacked in the same column.
+pub struct ColumnCache {
+    entries: Mutex<Entries>,
+    hits: AtomicU64,
+    misses: AtomicU64,
+}
+
+struct Entries {
+    map: HashMap<ColumnKey, Entry>,
+    /// Keys by when they were last used, the least recently used first.
+    order: BTreeMap<u64, ColumnKey>,
+    capacity: usize,
+    clock: u64,
+}
+
+impl ColumnCache {
+    pub const DEFAULT_CAPACITY: usize = 2048;
+
+    pub fn new(capacity: usize) -> Self {
+        ColumnCache {
+            entries: Mutex::new(Entries {
+                map: HashMap::new(),
+                order: BTreeMap::new(),
+                capacity,
+                clock: 0,
+            }),
+            hits: AtomicU64::new(0),
+            misses: AtomicU64::new(0),
+        }
+    }
+
+    pub fn set_capacity(&self, capacity: usize) {
+        let mut entries = self.entries.lock().unwrap();
+        entries.capacity = capacity;
+        entries.evict();
+    }
+
+    /// Total number of lookups which found a cached facet.
+    pub fn hits(&self) -> u64 {
+        self.hits.load(Ordering::Relaxed)
+    }
+
+    /// Total number of lookups which had to compute the facet.
+    pub fn misses(&self) -> u64 {
+        self.misses.load(Ordering::Relaxed)
+    }
+
+    /// The cached facet of the column of `area`, computed with `provide` if missing.
+    pub(super) fn get_or_insert_with(
+        &self,
+        facet: FacetId,
+        area: Extent3i,
+        seed: Seed,
+        provide: impl FnOnce() -> FacetValue,
+    ) -> FacetValue {
+        let key = ColumnKey {
+            facet,
+            column: [
+                area.minimum.x(),
+                area.minimum.z(),
+                area.shape.x(),
+                area.shape.z(),
+            ],
+            seed,
+        };
+        if let Some(value) = self.entries.lock().unwrap().get(&key) {
+            self.hits.fetch_add(1, Ordering::Relaxed);
+            return value;
+        }
+        self.misses.fetch_add(1, Ordering::Relaxed);
+        let value = provide();
+        self.entries.lock().unwrap().insert(key, value.clone());
+        value
+    }
+}
+
+impl Default for ColumnCache {
+    fn default() -> Self {
+        ColumnCache::new(ColumnCache::DEFAULT_CAPACITY)
+    }
+}
+
+impl Entries {
+    fn get(&mut self, key: &ColumnKey) -> Option<FacetValue> {
+        let entry = self.map.get_mut(key)?;
+        self.clock += 1;
+        self.order.remove(&entry.last_used);
+        self.order.insert(self.clock, *key);
+        entry.last_used = self.clock;
+        Some(entry.value.clone())
+    }
+
+    fn insert(&mut self, key: ColumnKey, value: FacetValue) {
+        self.clock += 1;
+        let last_used = self.clock;
+        if let Some(old) = self.map.insert(key, Entry { value, last_used }) {
+            self.order.remove(&old.last_used);
+        }
+        self.order.insert(last_used, key);
+        self.evict();
+    }
+
+    fn evict(&mut self) {
+        while self.map.len() > self.capacity {
+            let oldest = match self.order.keys().next() {
+                Some(last_used) => *last_used,
+                None => break,
+            };
+            if let Some(key) = self.order.remove(&oldest) {
+                self.map.remove(&key);
+            }
+        }
+    }
+}
+
+pub(super) fn setup_diagnostics(diagnostics: Option<ResMut<Diagnostics>>) {
+    if let Some(mut diagnostics) = diagnostics {
+        diagnostics.add(Diagnostic::new(COLUMN_CACHE_HITS, "column_cache_hits", 20));
+        diagnostics.add(Diagnostic::new(
+            COLUMN_CACHE_MISSES,
+            "column_cache_misses",
+            20,
+        ));
+    }
+}
+
+/// Reports the hits and misses since the last frame.
+pub(super) fn diagnostics(
+    registry: Res<FacetRegistry>,
+    diagnostics: Option<ResMut<Diagnostics>>,
+    mut last: Local<(u64, u64)>,
+) {
+    if let Some(mut diagnostics) = diagnostics {
+        let cache = registry.column_cache();
+        let (hits, misses) = (cache.hits(), cache.misses());
+        diagnostics.add_measurement(COLUMN_CACHE_HITS, (hits - last.0) as f64);
+        diagnostics.add_measurement(COLUMN_CACHE_MISSES, (misses - last.1) as f64);
+        *last = (hits, misses);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use building_blocks::core::PointN;
+
+    use super::*;
+
+    struct TestFacet;
+
+    fn lookup(cache: &ColumnCache, column: [i32; 2], y: i32) -> bool {
+        let area = Extent3i::from_min_and_shape(PointN([column[0], y, column[1]]), PointN([32; 3]));
+        let mut computed = false;
+        cache.get_or_insert_with(FacetId::of::<TestFacet>(), area, 0, || {
+            computed = true;
+            Arc::new(())
+        });
+        !computed
+    }
+
+    #[test]
+    fn chunks_in_the_same_column_share_facets() {
+        let cache = ColumnCache::new(8);
+        assert!(!lookup(&cache, [0, 0], 0));
+        assert!(lookup(&cache, [0, 0], 32));
+        assert!(lookup(&cache, [0, 0], -64));
+        assert!(!lookup(&cache, [32, 0], 0));
+        assert_eq!((cache.hits(), cache.misses()), (2, 2));
+    }
+
+    #[test]
+    fn least_recently_used_columns_are_evicted() {
+        let cache = ColumnCache::new(2);
+        lookup(&cache, [0, 0], 0);
+        lookup(&cache, [32, 0], 0);
+        // Used again, so the next insert evicts [32, 0] instead.
+        assert!(lookup(&cache, [0, 0], 0));
+        lookup(&cache, [64, 0], 0);
+        assert_eq!(cache.entries.lock().unwrap().map.len(), 2);
+        assert!(lookup(&cache, [0, 0], 0));
+        assert!(lookup(&cache, [64, 0], 0));
+        assert!(!lookup(&cache, [32, 0], 0));
+
+        cache.set_capacity(1);
+        let entries = cache.entries.lock().unwrap();
+        assert_eq!((entries.map.len(), entries.order.len()), (1, 1));
+    }
+}
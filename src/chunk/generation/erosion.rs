@@ -0,0 +1,353 @@
+use std::collections::{HashMap, VecDeque};
+use std::sync::{Arc, Mutex};
+
+use building_blocks::core::{Extent2i, PointN};
+use noise::{NoiseFn, Point2};
+
+use super::noise_elevation;
+
+/// Regions are anchored on a grid with this spacing, each one covers twice that size.
+const REGION_SIZE: i32 = 256;
+/// Distance between two points of the coarse heightmap in blocks.
+const CELL_SIZE: i32 = 4;
+/// Number of points along one side of a region heightmap.
+const SIDE: usize = (2 * REGION_SIZE / CELL_SIZE) as usize + 1;
+const MAX_CACHED_REGIONS: usize = 64;
+
+#[derive(Clone)]
+pub struct ErosionSettings {
+    pub enabled: bool,
+    /// Number of simulated raindrops per region.
+    pub droplets: u32,
+    /// Maximum number of steps a raindrop takes before it evaporates.
+    pub droplet_lifetime: u32,
+    /// How much a raindrop keeps its direction instead of following the slope.
+    pub inertia: f32,
+    pub sediment_capacity: f32,
+    pub min_sediment_capacity: f32,
+    pub erode_speed: f32,
+    pub deposit_speed: f32,
+    pub evaporate_speed: f32,
+    pub gravity: f32,
+    pub thermal_iterations: u32,
+    /// Height difference in blocks between two heightmap points above which material slides.
+    pub talus: f32,
+}
+
+impl Default for ErosionSettings {
+    fn default() -> Self {
+        ErosionSettings {
+            enabled: false,
+            droplets: 40_000,
+            droplet_lifetime: 40,
+            inertia: 0.05,
+            sediment_capacity: 4.0,
+            min_sediment_capacity: 0.01,
+            erode_speed: 0.3,
+            deposit_speed: 0.3,
+            evaporate_speed: 0.02,
+            gravity: 4.0,
+            thermal_iterations: 8,
+            talus: 4.0,
+        }
+    }
+}
+
+/// Optional erosion pass applied to the noise elevation.
+///
+/// Every region is eroded on its own coarse heightmap and the resulting height changes are
+/// blended between the four regions overlapping a column, so chunks never see a seam.
+#[derive(Default)]
+pub struct Erosion {
+    pub settings: ErosionSettings,
+    cache: Mutex<RegionCache>,
+}
+
+#[derive(Default)]
+struct RegionCache {
+    regions: HashMap<([i32; 2], u32), Arc<ErodedRegion>>,
+    order: VecDeque<([i32; 2], u32)>,
+}
+
+impl Erosion {
+    pub fn new(settings: ErosionSettings) -> Self {
+        Erosion {
+            settings,
+            cache: Default::default(),
+        }
+    }
+
+    /// All regions overlapping `columns`, eroded on demand.
+    pub(super) fn regions(
+        &self,
+        columns: Extent2i,
+        noise: &dyn NoiseFn<Point2<f64>>,
+        sea_level: f64,
+        seed: u32,
+    ) -> Vec<Arc<ErodedRegion>> {
+        let anchor = |pos: PointN<[i32; 2]>| {
+            PointN([
+                pos.x().div_euclid(REGION_SIZE),
+                pos.y().div_euclid(REGION_SIZE),
+            ])
+        };
+        let min = anchor(columns.minimum);
+        let max = anchor(columns.max()) + PointN([1, 1]);
+        Extent2i::from_min_and_max(min, max)
+            .iter_points()
+            .map(|anchor| self.region([anchor.x(), anchor.y()], noise, sea_level, seed))
+            .collect()
+    }
+
+    fn region(
+        &self,
+        anchor: [i32; 2],
+        noise: &dyn NoiseFn<Point2<f64>>,
+        sea_level: f64,
+        seed: u32,
+    ) -> Arc<ErodedRegion> {
+        let key = (anchor, seed);
+        if let Some(region) = self.cache.lock().unwrap().regions.get(&key) {
+            return region.clone();
+        }
+        let region = Arc::new(ErodedRegion::new(
+            anchor,
+            &self.settings,
+            noise,
+            sea_level,
+            seed,
+        ));
+
+        let mut cache = self.cache.lock().unwrap();
+        if cache.regions.insert(key, region.clone()).is_none() {
+            cache.order.push_back(key);
+        }
+        while cache.order.len() > MAX_CACHED_REGIONS {
+            if let Some(oldest) = cache.order.pop_front() {
+                cache.regions.remove(&oldest);
+            }
+        }
+        region
+    }
+}
+
+/// Height changes caused by erosion around one region anchor.
+pub(super) struct ErodedRegion {
+    /// World position of the heightmap's first point.
+    origin: [i32; 2],
+    /// Center of the region, where its blending weight is 1.0.
+    center: [f64; 2],
+    delta: Vec<f32>,
+}
+
+impl ErodedRegion {
+    fn new(
+        anchor: [i32; 2],
+        settings: &ErosionSettings,
+        noise: &dyn NoiseFn<Point2<f64>>,
+        sea_level: f64,
+        seed: u32,
+    ) -> Self {
+        let center = [anchor[0] * REGION_SIZE, anchor[1] * REGION_SIZE];
+        let origin = [center[0] - REGION_SIZE, center[1] - REGION_SIZE];
+
+        let mut heights = Heightmap::new(SIDE);
+        for z in 0..SIDE {
+            for x in 0..SIDE {
+                let world_x = (origin[0] + x as i32 * CELL_SIZE) as f64;
+                let world_z = (origin[1] + z as i32 * CELL_SIZE) as f64;
+                *heights.get_mut(x, z) = noise_elevation(noise, sea_level, world_x, world_z) as f32;
+            }
+        }
+        let original = heights.clone();
+
+        let mut random = Random::new(anchor, seed);
+        hydraulic(&mut heights, settings, &mut random);
+        thermal(&mut heights, settings);
+
+        let delta = heights
+            .data
+            .iter()
+            .zip(original.data.iter())
+            .map(|(eroded, original)| eroded - original)
+            .collect();
+        ErodedRegion {
+            origin,
+            center: [center[0] as f64, center[1] as f64],
+            delta,
+        }
+    }
+
+    /// Height change at a column, weighted so the four regions around it sum up to 1.0.
+    pub(super) fn weighted_delta(&self, x: f64, z: f64) -> f64 {
+        let size = REGION_SIZE as f64;
+        let weight_x = 1.0 - (x - self.center[0]).abs() / size;
+        let weight_z = 1.0 - (z - self.center[1]).abs() / size;
+        if weight_x <= 0.0 || weight_z <= 0.0 {
+            return 0.0;
+        }
+
+        let local_x = (x - self.origin[0] as f64) / CELL_SIZE as f64;
+        let local_z = (z - self.origin[1] as f64) / CELL_SIZE as f64;
+        let x0 = (local_x.floor() as usize).min(SIDE - 2);
+        let z0 = (local_z.floor() as usize).min(SIDE - 2);
+        let tx = local_x - x0 as f64;
+        let tz = local_z - z0 as f64;
+        let at = |x: usize, z: usize| self.delta[z * SIDE + x] as f64;
+        let delta = super::math::bi_lerp(
+            at(x0, z0),
+            at(x0 + 1, z0),
+            at(x0, z0 + 1),
+            at(x0 + 1, z0 + 1),
+            tx,
+            tz,
+        );
+        delta * weight_x * weight_z
+    }
+}
+
+#[derive(Clone)]
+struct Heightmap {
+    side: usize,
+    data: Vec<f32>,
+}
+
+impl Heightmap {
+    fn new(side: usize) -> Self {
+        Heightmap {
+            side,
+            data: vec![0.0; side * side],
+        }
+    }
+
+    fn get(&self, x: usize, z: usize) -> f32 {
+        self.data[z * self.side + x]
+    }
+
+    fn get_mut(&mut self, x: usize, z: usize) -> &mut f32 {
+        &mut self.data[z * self.side + x]
+    }
+
+    /// Interpolated height and gradient at a point between heightmap points.
+    fn sample(&self, x: f32, z: f32) -> (f32, [f32; 2]) {
+        let (x0, z0) = (x as usize, z as usize);
+        let (u, v) = (x - x0 as f32, z - z0 as f32);
+        let h00 = self.get(x0, z0);
+        let h10 = self.get(x0 + 1, z0);
+        let h01 = self.get(x0, z0 + 1);
+        let h11 = self.get(x0 + 1, z0 + 1);
+
+        let gradient_x = (h10 - h00) * (1.0 - v) + (h11 - h01) * v;
+        let gradient_z = (h01 - h00) * (1.0 - u) + (h11 - h10) * u;
+        let height =
+            h00 * (1.0 - u) * (1.0 - v) + h10 * u * (1.0 - v) + h01 * (1.0 - u) * v + h11 * u * v;
+        (height, [gradient_x, gradient_z])
+    }
+
+    /// Adds `amount` to the four points around `(x, z)`, weighted by distance.
+    fn deposit(&mut self, x: f32, z: f32, amount: f32) {
+        let (x0, z0) = (x as usize, z as usize);
+        let (u, v) = (x - x0 as f32, z - z0 as f32);
+        *self.get_mut(x0, z0) += amount * (1.0 - u) * (1.0 - v);
+        *self.get_mut(x0 + 1, z0) += amount * u * (1.0 - v);
+        *self.get_mut(x0, z0 + 1) += amount * (1.0 - u) * v;
+        *self.get_mut(x0 + 1, z0 + 1) += amount * u * v;
+    }
+}
+
+/// Droplet based hydraulic erosion.
+fn hydraulic(heights: &mut Heightmap, settings: &ErosionSettings, random: &mut Random) {
+    let limit = (heights.side - 1) as f32;
+    for _ in 0..settings.droplets {
+        let mut pos = [random.next() * limit, random.next() * limit];
+        let mut dir = [0.0f32; 2];
+        let mut speed = 1.0f32;
+        let mut water = 1.0f32;
+        let mut sediment = 0.0f32;
+
+        for _ in 0..settings.droplet_lifetime {
+            let (height, gradient) = heights.sample(pos[0], pos[1]);
+            dir[0] = dir[0] * settings.inertia - gradient[0] * (1.0 - settings.inertia);
+            dir[1] = dir[1] * settings.inertia - gradient[1] * (1.0 - settings.inertia);
+            let length = (dir[0] * dir[0] + dir[1] * dir[1]).sqrt();
+            if length < f32::EPSILON {
+                break;
+            }
+            dir = [dir[0] / length, dir[1] / length];
+
+            let old_pos = pos;
+            pos = [pos[0] + dir[0], pos[1] + dir[1]];
+            if pos[0] < 0.0 || pos[1] < 0.0 || pos[0] >= limit || pos[1] >= limit {
+                break;
+            }
+
+            let (new_height, _) = heights.sample(pos[0], pos[1]);
+            let delta_height = new_height - height;
+            let capacity = f32::max(
+                -delta_height * speed * water * settings.sediment_capacity,
+                settings.min_sediment_capacity,
+            );
+
+            if sediment > capacity || delta_height > 0.0 {
+                let amount = if delta_height > 0.0 {
+                    delta_height.min(sediment)
+                } else {
+                    (sediment - capacity) * settings.deposit_speed
+                };
+                sediment -= amount;
+                heights.deposit(old_pos[0], old_pos[1], amount);
+            } else {
+                let amount = ((capacity - sediment) * settings.erode_speed).min(-delta_height);
+                sediment += amount;
+                heights.deposit(old_pos[0], old_pos[1], -amount);
+            }
+
+            speed = (speed * speed - delta_height * settings.gravity)
+                .max(0.0)
+                .sqrt();
+            water *= 1.0 - settings.evaporate_speed;
+        }
+    }
+}
+
+/// Moves material down slopes steeper than the talus angle.
+fn thermal(heights: &mut Heightmap, settings: &ErosionSettings) {
+    let side = heights.side;
+    let mut changes = vec![0.0f32; side * side];
+    for _ in 0..settings.thermal_iterations {
+        changes.iter_mut().for_each(|change| *change = 0.0);
+        for z in 1..side - 1 {
+            for x in 1..side - 1 {
+                let height = heights.get(x, z);
+                for (nx, nz) in [(x + 1, z), (x - 1, z), (x, z + 1), (x, z - 1)] {
+                    let difference = height - heights.get(nx, nz);
+                    if difference > settings.talus {
+                        let amount = (difference - settings.talus) * 0.125;
+                        changes[z * side + x] -= amount;
+                        changes[nz * side + nx] += amount;
+                    }
+                }
+            }
+        }
+        for (height, change) in heights.data.iter_mut().zip(changes.iter()) {
+            *height += change;
+        }
+    }
+}
+
+/// Small deterministic generator so a region always erodes the same way.
+struct Random(u64);
+
+impl Random {
+    fn new(anchor: [i32; 2], seed: u32) -> Self {
+        let state = (((anchor[0] as u32 as u64) << 32) | anchor[1] as u32 as u64) ^ seed as u64;
+        Random(state.wrapping_mul(0x9e37_79b9_7f4a_7c15) | 1)
+    }
+
+    fn next(&mut self) -> f32 {
+        self.0 ^= self.0 << 13;
+        self.0 ^= self.0 >> 7;
+        self.0 ^= self.0 << 17;
+        (self.0 >> 40) as f32 / (1u64 << 24) as f32
+    }
+}
@@ -0,0 +1,479 @@
+use std::any::{type_name, Any, TypeId};
+use std::collections::HashMap;
+use std::fmt::{Display, Formatter};
+use std::hash::{Hash, Hasher};
+use std::sync::Arc;
+
+use bevy::prelude::*;
+use building_blocks::core::Extent3i;
+
+use super::column_cache::{ColumnCache, FacetValue};
+use super::{GeneratorSettings, NoiseGraph, NoiseGraphs, WorldPreset, DEFAULT_SEED};
+
+/// Identifies a facet by its type.
+#[derive(Debug, Clone, Copy)]
+pub struct FacetId {
+    id: TypeId,
+    name: &'static str,
+}
+
+impl FacetId {
+    pub fn of<T: 'static>() -> Self {
+        FacetId {
+            id: TypeId::of::<T>(),
+            name: type_name::<T>(),
+        }
+    }
+
+    pub fn name(&self) -> &'static str {
+        self.name
+    }
+}
+
+impl PartialEq for FacetId {
+    fn eq(&self, other: &Self) -> bool {
+        self.id == other.id
+    }
+}
+
+impl Eq for FacetId {}
+
+impl Hash for FacetId {
+    fn hash<H: Hasher>(&self, state: &mut H) {
+        self.id.hash(state)
+    }
+}
+
+type ProvideFn = dyn Fn(&FacetContext) -> FacetValue + Send + Sync;
+
+/// Computes one facet of a generating area from the facets it depends on.
+#[derive(Clone)]
+pub struct FacetProvider {
+    facet: FacetId,
+    dependencies: Vec<FacetId>,
+    per_column: bool,
+    provide: Arc<ProvideFn>,
+}
+
+impl FacetProvider {
+    pub fn new<T, F>(provide: F) -> Self
+    where
+        T: Send + Sync + 'static,
+        F: Fn(&FacetContext) -> T + Send + Sync + 'static,
+    {
+        FacetProvider {
+            facet: FacetId::of::<T>(),
+            dependencies: Vec::new(),
+            per_column: false,
+            provide: Arc::new(move |context: &FacetContext| -> FacetValue {
+                Arc::new(provide(context))
+            }),
+        }
+    }
+
+    /// Declares that the facet only depends on the columns of an area, so it is computed once
+    /// and shared by all chunks stacked in the same column.
+    pub fn per_column(mut self) -> Self {
+        self.per_column = true;
+        self
+    }
+
+    /// Declares that this provider reads facet `T`, which then has to be provided first.
+    pub fn after<T: 'static>(mut self) -> Self {
+        self.dependencies.push(FacetId::of::<T>());
+        self
+    }
+}
+
+/// What a provider can see while computing its facet.
+pub struct FacetContext<'a> {
+    area: Extent3i,
+    dependencies: &'a [FacetId],
+    facets: &'a Facets,
+    resources: &'a GeneratorResources,
+}
+
+impl<'a> FacetContext<'a> {
+    /// Voxels of the generating area.
+    pub fn area(&self) -> Extent3i {
+        self.area
+    }
+
+    /// A facet this provider declared as a dependency.
+    pub fn facet<T: 'static>(&self) -> &T {
+        let id = FacetId::of::<T>();
+        assert!(
+            self.dependencies.contains(&id),
+            "Facet {} is read without being declared as a dependency",
+            id.name
+        );
+        self.facets
+            .get::<T>()
+            .unwrap_or_else(|| panic!("Facet {} was not provided", id.name))
+    }
+
+    pub fn resource<R: 'static>(&self) -> Option<&R> {
+        self.resources.get::<R>()
+    }
+
+    /// The noise graph the world preset names `name`, if any.
+    pub fn noise_graph(&self, name: &str) -> Option<&NoiseGraph> {
+        self.resource::<NoiseGraphs>()
+            .and_then(|graphs| graphs.get(name))
+    }
+}
+
+/// All facets computed for one generating area.
+#[derive(Default)]
+pub struct Facets {
+    map: HashMap<FacetId, FacetValue>,
+}
+
+impl Facets {
+    pub fn get<T: 'static>(&self) -> Option<&T> {
+        self.map.get(&FacetId::of::<T>())?.downcast_ref()
+    }
+}
+
+/// Shared state providers can read, such as settings or loaded heightmaps.
+#[derive(Default, Clone)]
+pub struct GeneratorResources {
+    map: HashMap<TypeId, Arc<dyn Any + Send + Sync>>,
+}
+
+impl GeneratorResources {
+    pub fn insert<R: Send + Sync + 'static>(&mut self, resource: R) {
+        self.map.insert(TypeId::of::<R>(), Arc::new(resource));
+    }
+
+    pub fn contains<R: 'static>(&self) -> bool {
+        self.map.contains_key(&TypeId::of::<R>())
+    }
+
+    pub fn get<R: 'static>(&self) -> Option<&R> {
+        self.map.get(&TypeId::of::<R>())?.downcast_ref()
+    }
+}
+
+#[derive(Debug)]
+pub enum FacetGraphError {
+    Missing {
+        facet: &'static str,
+        required_by: &'static str,
+    },
+    Cycle(Vec<&'static str>),
+    /// A facet computed per column reads a facet which varies along the height.
+    NotPerColumn {
+        facet: &'static str,
+        dependency: &'static str,
+    },
+}
+
+impl Display for FacetGraphError {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        match self {
+            FacetGraphError::Missing { facet, required_by } => {
+                write!(
+                    f,
+                    "No provider for facet {} required by {}",
+                    facet, required_by
+                )
+            }
+            FacetGraphError::Cycle(cycle) => {
+                write!(f, "Facets depend on each other: {}", cycle.join(" -> "))
+            }
+            FacetGraphError::NotPerColumn { facet, dependency } => {
+                write!(
+                    f,
+                    "Facet {} is computed per column but depends on {} which is not",
+                    facet, dependency
+                )
+            }
+        }
+    }
+}
+
+impl std::error::Error for FacetGraphError {}
+
+/// Providers of every facet, run in dependency order for each generating area.
+#[derive(Default, Clone)]
+pub struct FacetRegistry {
+    providers: HashMap<FacetId, FacetProvider>,
+    /// Facets read by the rasterizer.
+    required: Vec<FacetId>,
+    order: Vec<FacetId>,
+    column_cache: Arc<ColumnCache>,
+}
+
+impl FacetRegistry {
+    /// Adds a provider, replacing the previous provider of the same facet.
+    pub fn add(&mut self, provider: FacetProvider) {
+        if let Some(previous) = self.providers.insert(provider.facet, provider) {
+            debug!("Replaced provider of facet {}", previous.facet.name);
+        }
+        self.order.clear();
+    }
+
+    pub fn require<T: 'static>(&mut self) {
+        self.required.push(FacetId::of::<T>());
+        self.order.clear();
+    }
+
+    /// Checks the dependency graph and computes the order providers run in.
+    pub fn schedule(&mut self) -> Result<(), FacetGraphError> {
+        #[derive(Clone, Copy, PartialEq)]
+        enum Mark {
+            Visiting,
+            Done,
+        }
+
+        fn visit(
+            facet: FacetId,
+            required_by: &'static str,
+            providers: &HashMap<FacetId, FacetProvider>,
+            marks: &mut HashMap<FacetId, Mark>,
+            path: &mut Vec<FacetId>,
+            order: &mut Vec<FacetId>,
+        ) -> Result<(), FacetGraphError> {
+            match marks.get(&facet) {
+                Some(Mark::Done) => return Ok(()),
+                Some(Mark::Visiting) => {
+                    let start = path.iter().position(|id| *id == facet).unwrap_or(0);
+                    let mut cycle: Vec<_> = path[start..].iter().map(|id| id.name).collect();
+                    cycle.push(facet.name);
+                    return Err(FacetGraphError::Cycle(cycle));
+                }
+                None => {}
+            }
+            let provider = providers.get(&facet).ok_or(FacetGraphError::Missing {
+                facet: facet.name,
+                required_by,
+            })?;
+
+            marks.insert(facet, Mark::Visiting);
+            path.push(facet);
+            for dependency in provider.dependencies.iter() {
+                visit(*dependency, facet.name, providers, marks, path, order)?;
+                if provider.per_column && !providers[dependency].per_column {
+                    return Err(FacetGraphError::NotPerColumn {
+                        facet: facet.name,
+                        dependency: dependency.name,
+                    });
+                }
+            }
+            path.pop();
+            marks.insert(facet, Mark::Done);
+            order.push(facet);
+            Ok(())
+        }
+
+        let mut marks = HashMap::new();
+        let mut order = Vec::new();
+        let mut path = Vec::new();
+        let mut provided: Vec<_> = self.providers.keys().copied().collect();
+        provided.sort_by_key(|id| id.name);
+        let mut facets = self.required.clone();
+        facets.extend(provided);
+        for facet in facets {
+            visit(
+                facet,
+                "the rasterizer",
+                &self.providers,
+                &mut marks,
+                &mut path,
+                &mut order,
+            )?;
+        }
+        self.order = order;
+        Ok(())
+    }
+
+    /// Runs every provider for `area`, reusing cached per column facets.
+    pub fn provide(&self, area: Extent3i, resources: &GeneratorResources) -> Facets {
+        let seed = resources
+            .get::<WorldPreset>()
+            .map_or(DEFAULT_SEED, |preset| preset.seed);
+        let mut facets = Facets::default();
+        for facet in self.order.iter() {
+            let provider = &self.providers[facet];
+            let context = FacetContext {
+                area,
+                dependencies: &provider.dependencies,
+                facets: &facets,
+                resources,
+            };
+            let value = if provider.per_column {
+                self.column_cache
+                    .get_or_insert_with(*facet, area, seed, || (provider.provide)(&context))
+            } else {
+                (provider.provide)(&context)
+            };
+            facets.map.insert(*facet, value);
+        }
+        facets
+    }
+
+    pub fn column_cache(&self) -> &ColumnCache {
+        &self.column_cache
+    }
+}
+
+pub trait FacetAppExt {
+    /// Registers the provider of a facet, replacing the previous one if there is any.
+    ///
+    /// Plugins replacing built-in providers have to be added after `ChunkGeneratorPlugin`.
+    fn add_facet_provider(&mut self, provider: FacetProvider) -> &mut Self;
+
+    /// Makes `R` available to providers through [`FacetContext::resource`].
+    fn insert_generator_resource<R: Send + Sync + 'static>(&mut self, resource: R) -> &mut Self;
+}
+
+impl FacetAppExt for App {
+    fn add_facet_provider(&mut self, provider: FacetProvider) -> &mut Self {
+        self.world
+            .get_resource_or_insert_with(FacetRegistry::default)
+            .add(provider);
+        self
+    }
+
+    fn insert_generator_resource<R: Send + Sync + 'static>(&mut self, resource: R) -> &mut Self {
+        self.world
+            .get_resource_or_insert_with(GeneratorResources::default)
+            .insert(resource);
+        self
+    }
+}
+
+pub(super) fn schedule_facets(
+    mut registry: ResMut<FacetRegistry>,
+    settings: Res<GeneratorSettings>,
+) {
+    registry
+        .column_cache
+        .set_capacity(settings.column_cache_capacity);
+    if let Err(e) = registry.schedule() {
+        panic!("Invalid world generator: {}", e);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use building_blocks::core::PointN;
+
+    use super::*;
+
+    struct A;
+    struct B;
+    struct C;
+
+    fn with_providers(providers: Vec<FacetProvider>) -> FacetRegistry {
+        let mut registry = FacetRegistry::default();
+        for provider in providers {
+            registry.add(provider);
+        }
+        registry
+    }
+
+    fn position<T: 'static>(registry: &FacetRegistry) -> usize {
+        let id = FacetId::of::<T>();
+        registry
+            .order
+            .iter()
+            .position(|facet| *facet == id)
+            .unwrap()
+    }
+
+    #[test]
+    fn providers_run_after_their_dependencies() {
+        let mut registry = with_providers(vec![
+            FacetProvider::new(|_| C).after::<B>(),
+            FacetProvider::new(|_| A),
+            FacetProvider::new(|_| B).after::<A>(),
+        ]);
+        registry.require::<C>();
+        registry.schedule().unwrap();
+        assert_eq!(registry.order.len(), 3);
+        assert!(position::<A>(&registry) < position::<B>(&registry));
+        assert!(position::<B>(&registry) < position::<C>(&registry));
+    }
+
+    #[test]
+    fn missing_providers_are_reported() {
+        let mut registry = with_providers(vec![FacetProvider::new(|_| B).after::<A>()]);
+        match registry.schedule() {
+            Err(FacetGraphError::Missing { facet, required_by }) => {
+                assert_eq!(facet, type_name::<A>());
+                assert_eq!(required_by, type_name::<B>());
+            }
+            result => panic!("Expected a missing facet, got {:?}", result),
+        }
+
+        let mut registry = FacetRegistry::default();
+        registry.require::<C>();
+        match registry.schedule() {
+            Err(FacetGraphError::Missing { facet, required_by }) => {
+                assert_eq!(facet, type_name::<C>());
+                assert_eq!(required_by, "the rasterizer");
+            }
+            result => panic!("Expected a missing facet, got {:?}", result),
+        }
+    }
+
+    #[test]
+    fn cycles_are_reported() {
+        let mut registry = with_providers(vec![
+            FacetProvider::new(|_| A).after::<C>(),
+            FacetProvider::new(|_| B).after::<A>(),
+            FacetProvider::new(|_| C).after::<B>(),
+        ]);
+        match registry.schedule() {
+            Err(FacetGraphError::Cycle(cycle)) => {
+                assert_eq!(cycle.len(), 4);
+                assert_eq!(cycle.first(), cycle.last());
+                for name in [type_name::<A>(), type_name::<B>(), type_name::<C>()] {
+                    assert!(cycle.contains(&name), "{} is not in {:?}", name, cycle);
+                }
+            }
+            result => panic!("Expected a cycle, got {:?}", result),
+        }
+    }
+
+    #[test]
+    fn per_column_facets_only_read_per_column_facets() {
+        let mut registry = with_providers(vec![
+            FacetProvider::new(|_| A),
+            FacetProvider::new(|_| B).per_column().after::<A>(),
+        ]);
+        match registry.schedule() {
+            Err(FacetGraphError::NotPerColumn { facet, dependency }) => {
+                assert_eq!(facet, type_name::<B>());
+                assert_eq!(dependency, type_name::<A>());
+            }
+            result => panic!("Expected a per column error, got {:?}", result),
+        }
+
+        let mut registry = with_providers(vec![
+            FacetProvider::new(|_| A).per_column(),
+            FacetProvider::new(|_| B).per_column().after::<A>(),
+            FacetProvider::new(|_| C).after::<B>(),
+        ]);
+        registry.schedule().unwrap();
+    }
+
+    #[test]
+    #[should_panic(expected = "without being declared as a dependency")]
+    fn undeclared_dependencies_cannot_be_read() {
+        let mut registry = with_providers(vec![
+            FacetProvider::new(|_| A),
+            FacetProvider::new(|context| {
+                context.facet::<A>();
+                B
+            }),
+        ]);
+        // Scheduled in name order, A happens to be provided first.
+        registry.schedule().unwrap();
+        assert!(position::<A>(&registry) < position::<B>(&registry));
+        let area = Extent3i::from_min_and_shape(PointN([0; 3]), PointN([1; 3]));
+        registry.provide(area, &GeneratorResources::default());
+    }
+}
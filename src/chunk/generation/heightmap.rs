@@ -0,0 +1,201 @@
+use anyhow::bail;
+use bevy::prelude::*;
+use building_blocks::prelude::{Get, GetMut};
+use image::DynamicImage;
+use serde_derive::Deserialize;
+
+use super::hydrology::{LakeFacet, RiverFacet};
+use super::{
+    base_elevation, math, ElevationFacet, Facet2D, FacetContext, FacetProvider, FacetRegistry,
+    GeneratorResources, SeaLevel, WorldPreset,
+};
+
+/// Where and how a grayscale image is placed in the world.
+#[derive(Debug, Clone, Deserialize)]
+pub struct HeightmapSettings {
+    /// Path to an 8 or 16-bit grayscale PNG, relative to the preset file when loaded from one.
+    pub path: String,
+    /// Width of one pixel in blocks.
+    #[serde(default = "one")]
+    pub horizontal_scale: f64,
+    /// Height in blocks of a white pixel above a black one.
+    #[serde(default = "default_vertical_scale")]
+    pub vertical_scale: f64,
+    /// World column of the image's top left pixel.
+    #[serde(default)]
+    pub origin: [f64; 2],
+    /// Height of a black pixel.
+    #[serde(default)]
+    pub base_height: f64,
+    /// Width in blocks of the band inside the image's edges over which it fades into the noise
+    /// elevation around it.
+    #[serde(default = "default_blend_margin")]
+    pub blend_margin: f64,
+}
+
+fn one() -> f64 {
+    1.0
+}
+
+fn default_blend_margin() -> f64 {
+    32.0
+}
+
+fn default_vertical_scale() -> f64 {
+    128.0
+}
+
+/// Elevation read from an image, noise is used outside of it and blended in along its edges.
+pub struct Heightmap {
+    settings: HeightmapSettings,
+    width: usize,
+    height: usize,
+    /// Pixel values normalized to `0.0..=1.0`, row by row.
+    data: Vec<f32>,
+}
+
+impl Heightmap {
+    pub fn load(settings: HeightmapSettings) -> anyhow::Result<Self> {
+        let image = image::open(&settings.path)?;
+        let (width, height, data) = match image {
+            DynamicImage::ImageLuma8(image) => {
+                let data = image.pixels().map(|p| p.0[0] as f32 / 255.0).collect();
+                (image.width(), image.height(), data)
+            }
+            DynamicImage::ImageLuma16(image) => {
+                let data = image.pixels().map(|p| p.0[0] as f32 / 65535.0).collect();
+                (image.width(), image.height(), data)
+            }
+            _ => bail!(
+                "Heightmap {} is not an 8 or 16-bit grayscale image",
+                settings.path
+            ),
+        };
+        Ok(Heightmap {
+            settings,
+            width: width as usize,
+            height: height as usize,
+            data,
+        })
+    }
+
+    /// Position of a column in pixels.
+    fn pixel(&self, x: f64, z: f64) -> (f64, f64) {
+        (
+            (x - self.settings.origin[0]) / self.settings.horizontal_scale,
+            (z - self.settings.origin[1]) / self.settings.horizontal_scale,
+        )
+    }
+
+    pub(super) fn contains(&self, x: f64, z: f64) -> bool {
+        let (px, pz) = self.pixel(x, z);
+        px >= 0.0 && pz >= 0.0 && px <= (self.width - 1) as f64 && pz <= (self.height - 1) as f64
+    }
+
+    /// Whether the image covers any column within `radius` of `(x, z)`.
+    pub(super) fn overlaps(&self, x: f64, z: f64, radius: f64) -> bool {
+        let min = self.settings.origin;
+        let scale = self.settings.horizontal_scale;
+        let max = [
+            min[0] + (self.width - 1) as f64 * scale,
+            min[1] + (self.height - 1) as f64 * scale,
+        ];
+        x + radius >= min[0] && x - radius <= max[0] && z + radius >= min[1] && z - radius <= max[1]
+    }
+
+    /// How much the image weighs against the noise elevation, from zero at its edges and
+    /// outside of it to one `blend_margin` inside of them.
+    pub(super) fn weight(&self, x: f64, z: f64) -> f64 {
+        let (px, pz) = self.pixel(x, z);
+        // Distance to the closest edge in blocks.
+        let edge = px
+            .min(pz)
+            .min((self.width - 1) as f64 - px)
+            .min((self.height - 1) as f64 - pz)
+            * self.settings.horizontal_scale;
+        if edge <= 0.0 {
+            return 0.0;
+        }
+        if self.settings.blend_margin <= 0.0 {
+            return 1.0;
+        }
+        let t = (edge / self.settings.blend_margin).min(1.0);
+        t * t * (3.0 - 2.0 * t)
+    }
+
+    /// Bilinearly interpolated elevation, `None` outside of the image.
+    pub(super) fn elevation(&self, x: f64, z: f64) -> Option<f64> {
+        if !self.contains(x, z) {
+            return None;
+        }
+        let (px, pz) = self.pixel(x, z);
+        let x0 = (px.floor() as usize).min(self.width.saturating_sub(2));
+        let z0 = (pz.floor() as usize).min(self.height.saturating_sub(2));
+        let x1 = (x0 + 1).min(self.width - 1);
+        let z1 = (z0 + 1).min(self.height - 1);
+        let at = |x: usize, z: usize| self.data[z * self.width + x] as f64;
+        let value = super::math::bi_lerp(
+            at(x0, z0),
+            at(x1, z0),
+            at(x0, z1),
+            at(x1, z1),
+            px - x0 as f64,
+            pz - z0 as f64,
+        );
+        Some(self.settings.base_height + value * self.settings.vertical_scale)
+    }
+}
+
+/// Replaces the noise elevation with the heightmap when the world preset selects one.
+pub(super) fn load_heightmap(
+    preset: Res<WorldPreset>,
+    mut registry: ResMut<FacetRegistry>,
+    mut resources: ResMut<GeneratorResources>,
+) {
+    if let Some(settings) = &preset.heightmap {
+        match Heightmap::load(settings.clone()) {
+            Ok(heightmap) => {
+                info!(
+                    "Loaded {}x{} heightmap {}",
+                    heightmap.width, heightmap.height, settings.path
+                );
+                resources.insert(heightmap);
+                registry.add(
+                    FacetProvider::new(provide_heightmap_elevation_facet)
+                        .per_column()
+                        .after::<SeaLevel>()
+                        .after::<RiverFacet>()
+                        .after::<LakeFacet>(),
+                );
+            }
+            Err(e) => {
+                error!("Heightmap cannot be loaded, using noise instead {:?}", e);
+            }
+        }
+    }
+}
+
+fn provide_heightmap_elevation_facet(context: &FacetContext) -> ElevationFacet {
+    let heightmap = context.resource::<Heightmap>().unwrap();
+    let sea_level = context.facet::<SeaLevel>().0 as f64;
+    let rivers = &context.facet::<RiverFacet>().0;
+    let lakes = &context.facet::<LakeFacet>().0;
+
+    let mut facet = Facet2D::new(context.area());
+    // Blended with the elevation there would be without the heightmap, so the edges match it.
+    let base = base_elevation(context, *facet.data.extent());
+    for pos in facet.data.extent().iter_points() {
+        let value = facet.data.get_mut(pos);
+        let (x, z) = (pos.x() as f64, pos.y() as f64);
+        let mut height = match heightmap.elevation(x, z) {
+            Some(image) => math::lerp(base.get(pos), image, heightmap.weight(x, z)),
+            None => base.get(pos),
+        };
+        for lake in lakes.iter() {
+            height = lake.carve(height, x, z);
+        }
+        height = rivers.data.get(pos).carve(height, sea_level);
+        *value = height as i32;
+    }
+    ElevationFacet(facet)
+}
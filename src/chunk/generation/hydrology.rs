@@ -0,0 +1,261 @@
+use building_blocks::core::{Extent2i, PointN};
+use building_blocks::prelude::{Array2x1, Get, GetMut};
+use noise::{Fbm, MultiFractal, NoiseFn, Point2, Seedable};
+
+use super::heightmap::Heightmap;
+use super::{
+    elevation_noise, noise_elevation, world_seed, ElevationFacet, Facet2D, FacetContext, SeaLevel,
+    SubSampleNoise,
+};
+
+/// Depth of a river channel below the surrounding valley floor.
+const RIVER_DEPTH: f64 = 6.0;
+/// Distance between the valley floor and the river's water surface.
+const RIVER_BANK: i32 = 2;
+/// Width of the river channel in river noise units.
+const RIVER_WIDTH: f64 = 0.035;
+/// Width of the valley around a river in river noise units.
+const VALLEY_WIDTH: f64 = 0.15;
+
+/// Lakes are placed at most one per `LAKE_CELL` x `LAKE_CELL` cell.
+const LAKE_CELL: i32 = 256;
+const LAKE_CHANCE: f64 = 0.35;
+const LAKE_MIN_RADIUS: f64 = 20.0;
+const LAKE_MAX_RADIUS: f64 = 48.0;
+const LAKE_DEPTH: f64 = 8.0;
+/// Shore band around a lake, relative to its radius.
+const LAKE_SHORE: f64 = 0.3;
+/// Circle halfway through the shore which the lake level is taken from, relative to its radius.
+const LAKE_RIM: f64 = 1.0 + LAKE_SHORE * 0.5;
+/// Blocks between the points of the rim sampled for the lowest one.
+const RIM_SPACING: f64 = 2.0;
+
+pub(super) struct RiverFacet(pub(super) Facet2D<RiverSample>);
+
+pub(super) struct LakeFacet(pub(super) Vec<Lake>);
+
+/// Height of the water surface for every column, `SeaLevel` if there is no river or lake.
+pub struct WaterLevelFacet(pub Facet2D<i32>);
+
+#[derive(Default, Clone, Copy)]
+pub(super) struct RiverSample {
+    /// 1.0 in the middle of the river channel, 0.0 outside of it.
+    pub(super) channel: f32,
+    /// 1.0 in the middle of the valley, 0.0 outside of it.
+    pub(super) valley: f32,
+}
+
+impl RiverSample {
+    fn new(value: f64) -> Self {
+        let value = value.abs();
+        RiverSample {
+            channel: (1.0 - value / RIVER_WIDTH).max(0.0) as f32,
+            valley: (1.0 - value / VALLEY_WIDTH).max(0.0) as f32,
+        }
+    }
+
+    /// This river carved only `strength` as deep, from 0 to 1.
+    fn scaled(self, strength: f32) -> Self {
+        RiverSample {
+            channel: self.channel * strength,
+            valley: self.valley * strength,
+        }
+    }
+
+    /// Lowers `height` into the river valley and channel.
+    pub(super) fn carve(&self, height: f64, sea_level: f64) -> f64 {
+        let valley = self.valley as f64;
+        let valley_depth = (height - sea_level).max(0.0) * 0.5 * valley * valley;
+        height - valley_depth - RIVER_DEPTH * self.channel as f64
+    }
+}
+
+#[derive(Clone, Copy)]
+pub(super) struct Lake {
+    center: [f64; 2],
+    radius: f64,
+    level: i32,
+}
+
+impl Lake {
+    /// 1.0 inside the lake, falling to 0.0 at the outer edge of its shore.
+    pub(super) fn influence(&self, x: f64, z: f64) -> f64 {
+        let distance = ((x - self.center[0]).powi(2) + (z - self.center[1]).powi(2)).sqrt();
+        let shore = self.radius * LAKE_SHORE;
+        (1.0 - (distance - self.radius) / shore).clamp(0.0, 1.0)
+    }
+
+    /// Whether water stands at the lake level on a column of this height, only inside the rim
+    /// the level was taken from so that it never spills over.
+    pub(super) fn floods(&self, height: i32, x: f64, z: f64) -> bool {
+        let distance = ((x - self.center[0]).powi(2) + (z - self.center[1]).powi(2)).sqrt();
+        distance <= self.radius * LAKE_RIM && height < self.level
+    }
+
+    /// Lowers `height` into the lake basin, blending back to the terrain over the shore.
+    pub(super) fn carve(&self, height: f64, x: f64, z: f64) -> f64 {
+        let level = self.level as f64;
+        let distance = ((x - self.center[0]).powi(2) + (z - self.center[1]).powi(2)).sqrt();
+        if distance < self.radius {
+            let t = distance / self.radius;
+            height.min(level - LAKE_DEPTH * (1.0 - t * t))
+        } else {
+            let t = (distance - self.radius) / (self.radius * LAKE_SHORE);
+            if t < 1.0 {
+                height.min(level + (height - level) * t)
+            } else {
+                height
+            }
+        }
+    }
+}
+
+fn river_noise(seed: u32) -> Fbm {
+    Fbm::new().set_octaves(4).set_seed(seed.wrapping_add(30011))
+}
+
+pub(super) fn provide_river_facet(context: &FacetContext) -> RiverFacet {
+    let heightmap = context.resource::<Heightmap>();
+    let fbm = river_noise(world_seed(context));
+    let noise = SubSampleNoise::new(&fbm)
+        .set_scale([0.0015, 0.0015, 1.0])
+        .set_sample_rate(4);
+
+    let mut facet = RiverFacet(Facet2D::new(context.area()));
+    let mut values = Array2x1::fill(*facet.0.data.extent(), 0.0);
+    noise.fill(&mut values);
+    for pos in facet.0.data.extent().iter_points() {
+        let value = facet.0.data.get_mut(pos);
+        let (x, z) = (pos.x() as f64, pos.y() as f64);
+        // Rivers fade out over the margin of the heightmap instead of stopping at its edge.
+        let strength = heightmap.map_or(1.0, |heightmap| 1.0 - heightmap.weight(x, z));
+        *value = RiverSample::new(values.get(pos)).scaled(strength as f32);
+    }
+    facet
+}
+
+pub(super) fn provide_lake_facet(context: &FacetContext) -> LakeFacet {
+    let heightmap = context.resource::<Heightmap>();
+    let sea_level = context.facet::<SeaLevel>().0;
+    let seed = world_seed(context);
+    let fbm = elevation_noise(seed);
+    let built_in = SubSampleNoise::new(&fbm)
+        .set_scale([0.004, 0.004, 1.0])
+        .set_sample_rate(4);
+    // The rim is sampled from the same noise as the elevation.
+    let noise: &dyn NoiseFn<Point2<f64>> = match context.noise_graph("elevation") {
+        Some(graph) => graph,
+        None => &built_in,
+    };
+
+    let area = context.area();
+    let columns = Extent2i::from_min_and_shape(area.minimum.xz(), area.shape.xz());
+    let cell = |pos: PointN<[i32; 2]>| {
+        PointN([pos.x().div_euclid(LAKE_CELL), pos.y().div_euclid(LAKE_CELL)])
+    };
+    let cells = Extent2i::from_min_and_max(cell(columns.minimum), cell(columns.max()));
+
+    let lakes = cells
+        .iter_points()
+        .filter_map(|cell| lake_in_cell(noise, sea_level, seed, cell.x(), cell.y()))
+        .filter(|lake| {
+            heightmap.map_or(true, |heightmap| {
+                let reach = lake.radius * (1.0 + LAKE_SHORE);
+                !heightmap.overlaps(lake.center[0], lake.center[1], reach)
+            })
+        })
+        .collect();
+    LakeFacet(lakes)
+}
+
+/// Lakes are centered far enough from their cell borders that they never cross them,
+/// and sit below the lowest point of their rim so they do not spill over.
+fn lake_in_cell(
+    noise: &dyn NoiseFn<Point2<f64>>,
+    sea_level: i32,
+    seed: u32,
+    cell_x: i32,
+    cell_z: i32,
+) -> Option<Lake> {
+    let roll = |salt: u32| hash(cell_x, cell_z, salt, seed) as f64 / u32::MAX as f64;
+    if roll(0) > LAKE_CHANCE {
+        return None;
+    }
+    let cell_size = LAKE_CELL as f64;
+    let center = [
+        (cell_x as f64 + 0.25 + roll(1) * 0.5) * cell_size,
+        (cell_z as f64 + 0.25 + roll(2) * 0.5) * cell_size,
+    ];
+    let radius = LAKE_MIN_RADIUS + roll(3) * (LAKE_MAX_RADIUS - LAKE_MIN_RADIUS);
+
+    let rim = radius * LAKE_RIM;
+    let samples = (std::f64::consts::TAU * rim / RIM_SPACING).ceil() as usize;
+    let level = (0..samples)
+        .map(|i| {
+            let angle = i as f64 * std::f64::consts::TAU / samples as f64;
+            let x = center[0] + rim * angle.cos();
+            let z = center[1] + rim * angle.sin();
+            noise_elevation(noise, sea_level as f64, x, z)
+        })
+        .fold(f64::MAX, f64::min) as i32
+        - 1;
+
+    if level <= sea_level + 1 {
+        return None;
+    }
+    Some(Lake {
+        center,
+        radius,
+        level,
+    })
+}
+
+fn hash(x: i32, z: i32, salt: u32, seed: u32) -> u32 {
+    let mut h = (x as u32)
+        .wrapping_mul(0x8da6_b343)
+        .wrapping_add((z as u32).wrapping_mul(0xd816_3841))
+        .wrapping_add(salt.wrapping_mul(0xcb1a_b31f))
+        .wrapping_add(seed);
+    h ^= h >> 15;
+    h = h.wrapping_mul(0x2c1b_3c6d);
+    h ^= h >> 12;
+    h = h.wrapping_mul(0x297a_2d39);
+    h ^ (h >> 15)
+}
+
+pub(super) fn provide_water_level_facet(context: &FacetContext) -> WaterLevelFacet {
+    let sea_level = context.facet::<SeaLevel>().0;
+    let elevation = &context.facet::<ElevationFacet>().0;
+    let rivers = &context.facet::<RiverFacet>().0;
+    let lakes = &context.facet::<LakeFacet>().0;
+
+    let mut facet = WaterLevelFacet(Facet2D {
+        data: elevation.data.clone(),
+    });
+    for pos in facet.0.data.extent().iter_points() {
+        let value = facet.0.data.get_mut(pos);
+        let mut level = sea_level;
+        let height = elevation.data.get(pos);
+
+        let river = rivers.data.get(pos);
+        if river.channel > 0.0 {
+            let channel_depth = (RIVER_DEPTH * river.channel as f64) as i32;
+            level = level.max(height + channel_depth - RIVER_BANK);
+        }
+        for lake in lakes.iter() {
+            if lake.floods(height, pos.x() as f64, pos.y() as f64) {
+                level = level.max(lake.level);
+            }
+        }
+        *value = level;
+    }
+    facet
+}
+
+/// How strongly water flattens the terrain at a column, used to keep noise out of riverbeds.
+pub(super) fn wetness(river: RiverSample, lakes: &[Lake], x: f64, z: f64) -> f32 {
+    lakes
+        .iter()
+        .map(|lake| lake.influence(x, z) as f32)
+        .fold(river.valley.max(river.channel), f32::max)
+}
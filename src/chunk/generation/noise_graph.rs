@@ -0,0 +1,747 @@
+use std::cmp::Ordering;
+use std::collections::HashMap;
+use std::fmt::{Display, Formatter};
+use std::fs;
+use std::path::Path;
+
+use bevy::prelude::*;
+use building_blocks::prelude::{Array3x1, Get, GetMut};
+use noise::{Fbm, MultiFractal, NoiseFn, Point2, Point3, RidgedMulti, Seedable, SuperSimplex};
+use serde_derive::Deserialize;
+
+use super::{math, GeneratorResources, SubSampleNoise, WorldPreset};
+
+/// A noise graph as written in a `.noise.ron` file.
+///
+/// Nodes are named and read other nodes by name, `output` is the node the graph evaluates to.
+#[derive(Debug, Clone, Deserialize)]
+pub struct NoiseGraphDesc {
+    pub nodes: HashMap<String, NoiseNode>,
+    pub output: String,
+}
+
+#[derive(Debug, Clone, Deserialize)]
+pub enum NoiseNode {
+    Fbm(FractalDesc),
+    Ridged(FractalDesc),
+    SuperSimplex {
+        #[serde(default)]
+        seed: u32,
+    },
+    Constant(f64),
+    /// `source * scale + bias`.
+    ScaleBias {
+        source: String,
+        #[serde(default = "one")]
+        scale: f64,
+        #[serde(default)]
+        bias: f64,
+    },
+    Add(Vec<String>),
+    Multiply(Vec<String>),
+    Clamp {
+        source: String,
+        min: f64,
+        max: f64,
+    },
+    /// Maps the source through a cubic curve going through `points`, given as `(input, output)`.
+    Curve {
+        source: String,
+        points: Vec<(f64, f64)>,
+    },
+    /// `high` where `control` is within `bounds`, `low` elsewhere, blended over `falloff`.
+    Select {
+        low: String,
+        high: String,
+        control: String,
+        bounds: (f64, f64),
+        #[serde(default)]
+        falloff: f64,
+    },
+    /// Samples the source every `sample_rate` blocks and interpolates in between,
+    /// see [`SubSampleNoise`].
+    SubSample {
+        source: String,
+        #[serde(default = "unit_scale")]
+        scale: [f64; 3],
+        #[serde(default = "one_block")]
+        sample_rate: i32,
+    },
+}
+
+/// Parameters of a fractal source, the generator's defaults are used for missing ones.
+#[derive(Debug, Clone, Deserialize)]
+pub struct FractalDesc {
+    /// Added to the world seed.
+    #[serde(default)]
+    pub seed: u32,
+    #[serde(default)]
+    pub octaves: Option<usize>,
+    #[serde(default)]
+    pub frequency: Option<f64>,
+    #[serde(default)]
+    pub lacunarity: Option<f64>,
+    #[serde(default)]
+    pub persistence: Option<f64>,
+}
+
+fn one() -> f64 {
+    1.0
+}
+
+fn unit_scale() -> [f64; 3] {
+    [1.0; 3]
+}
+
+fn one_block() -> i32 {
+    1
+}
+
+#[derive(Debug)]
+pub enum NoiseGraphError {
+    MissingOutput(String),
+    UnknownSource { node: String, source: String },
+    Cycle(Vec<String>),
+    Invalid { node: String, reason: String },
+}
+
+impl Display for NoiseGraphError {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        match self {
+            NoiseGraphError::MissingOutput(output) => {
+                write!(f, "Output node `{}` does not exist", output)
+            }
+            NoiseGraphError::UnknownSource { node, source } => {
+                write!(f, "Node `{}` reads unknown node `{}`", node, source)
+            }
+            NoiseGraphError::Cycle(cycle) => {
+                write!(f, "Nodes depend on each other: {}", cycle.join(" -> "))
+            }
+            NoiseGraphError::Invalid { node, reason } => {
+                write!(f, "Node `{}` is invalid: {}", node, reason)
+            }
+        }
+    }
+}
+
+impl std::error::Error for NoiseGraphError {}
+
+/// Nodes read other nodes by their index, which is always lower than their own.
+enum Node {
+    Fbm(Fbm),
+    Ridged(RidgedMulti),
+    SuperSimplex(SuperSimplex),
+    Constant(f64),
+    ScaleBias {
+        source: usize,
+        scale: f64,
+        bias: f64,
+    },
+    Add(Vec<usize>),
+    Multiply(Vec<usize>),
+    Clamp {
+        source: usize,
+        min: f64,
+        max: f64,
+    },
+    Curve {
+        source: usize,
+        points: Vec<(f64, f64)>,
+    },
+    Select {
+        low: usize,
+        high: usize,
+        control: usize,
+        bounds: (f64, f64),
+        falloff: f64,
+    },
+    SubSample {
+        source: usize,
+        scale: [f64; 3],
+        sample_rate: i32,
+    },
+}
+
+/// A validated noise graph, seeded for one world.
+pub struct NoiseGraph {
+    nodes: Vec<Node>,
+    output: usize,
+}
+
+impl NoiseGraph {
+    pub fn load<P: AsRef<Path>>(path: P, seed: u32) -> anyhow::Result<Self> {
+        let str = fs::read_to_string(path)?;
+        let desc: NoiseGraphDesc = ron::from_str(&str)?;
+        Ok(NoiseGraph::new(&desc, seed)?)
+    }
+
+    pub fn new(desc: &NoiseGraphDesc, seed: u32) -> Result<Self, NoiseGraphError> {
+        if !desc.nodes.contains_key(&desc.output) {
+            return Err(NoiseGraphError::MissingOutput(desc.output.clone()));
+        }
+        let mut builder = Builder {
+            desc,
+            seed,
+            indices: HashMap::new(),
+            path: Vec::new(),
+            nodes: Vec::new(),
+        };
+        // Every node is checked, even the ones the output does not read.
+        let mut names: Vec<_> = desc.nodes.keys().collect();
+        names.sort();
+        for name in names {
+            builder.visit(name)?;
+        }
+        Ok(NoiseGraph {
+            output: builder.indices[&desc.output],
+            nodes: builder.nodes,
+        })
+    }
+
+    /// Fills `array` with the output of the graph at each of its points.
+    pub fn fill(&self, array: &mut Array3x1<f64>) {
+        self.fill_node(self.output, array);
+    }
+
+    fn get_node(&self, index: usize, point: Point3<f64>) -> f64 {
+        match &self.nodes[index] {
+            Node::Fbm(noise) => noise.get(point),
+            Node::Ridged(noise) => noise.get(point),
+            Node::SuperSimplex(noise) => noise.get(point),
+            Node::Constant(value) => *value,
+            Node::ScaleBias {
+                source,
+                scale,
+                bias,
+            } => self.get_node(*source, point) * scale + bias,
+            Node::Add(sources) => sources.iter().map(|s| self.get_node(*s, point)).sum(),
+            Node::Multiply(sources) => sources.iter().map(|s| self.get_node(*s, point)).product(),
+            Node::Clamp { source, min, max } => self.get_node(*source, point).clamp(*min, *max),
+            Node::Curve { source, points } => curve(points, self.get_node(*source, point)),
+            Node::Select {
+                low,
+                high,
+                control,
+                bounds,
+                falloff,
+            } => select(
+                || self.get_node(*low, point),
+                || self.get_node(*high, point),
+                self.get_node(*control, point),
+                *bounds,
+                *falloff,
+            ),
+            Node::SubSample {
+                source,
+                scale,
+                sample_rate,
+            } => {
+                let source = NodeRef {
+                    graph: self,
+                    index: *source,
+                };
+                SubSampleNoise::new(&source)
+                    .set_scale(*scale)
+                    .set_sample_rate(*sample_rate)
+                    .get(point)
+            }
+        }
+    }
+
+    /// Same as `get_node` for every point, but sub-sampled nodes only sample their lattice once.
+    fn fill_node(&self, index: usize, array: &mut Array3x1<f64>) {
+        let extent = *array.extent();
+        let other = |index: usize| {
+            let mut other = Array3x1::fill(extent, 0.0);
+            self.fill_node(index, &mut other);
+            other
+        };
+        match &self.nodes[index] {
+            Node::Fbm(_) | Node::Ridged(_) | Node::SuperSimplex(_) | Node::Constant(_) => {
+                for pos in extent.iter_points() {
+                    let point = [pos.x() as f64, pos.y() as f64, pos.z() as f64];
+                    *array.get_mut(pos) = self.get_node(index, point);
+                }
+            }
+            Node::ScaleBias {
+                source,
+                scale,
+                bias,
+            } => {
+                self.fill_node(*source, array);
+                map(array, |value| value * scale + bias);
+            }
+            Node::Add(sources) => {
+                self.fill_node(sources[0], array);
+                for source in sources[1..].iter() {
+                    let other = other(*source);
+                    for pos in extent.iter_points() {
+                        *array.get_mut(pos) += other.get(pos);
+                    }
+                }
+            }
+            Node::Multiply(sources) => {
+                self.fill_node(sources[0], array);
+                for source in sources[1..].iter() {
+                    let other = other(*source);
+                    for pos in extent.iter_points() {
+                        *array.get_mut(pos) *= other.get(pos);
+                    }
+                }
+            }
+            Node::Clamp { source, min, max } => {
+                self.fill_node(*source, array);
+                map(array, |value| value.clamp(*min, *max));
+            }
+            Node::Curve { source, points } => {
+                self.fill_node(*source, array);
+                map(array, |value| curve(points, value));
+            }
+            Node::Select {
+                low,
+                high,
+                control,
+                bounds,
+                falloff,
+            } => {
+                self.fill_node(*control, array);
+                let low = other(*low);
+                let high = other(*high);
+                for pos in extent.iter_points() {
+                    let value = array.get_mut(pos);
+                    *value = select(|| low.get(pos), || high.get(pos), *value, *bounds, *falloff);
+                }
+            }
+            Node::SubSample {
+                source,
+                scale,
+                sample_rate,
+            } => {
+                let source = NodeRef {
+                    graph: self,
+                    index: *source,
+                };
+                SubSampleNoise::new(&source)
+                    .set_scale(*scale)
+                    .set_sample_rate(*sample_rate)
+                    .fill(array);
+            }
+        }
+    }
+}
+
+impl NoiseFn<Point3<f64>> for NoiseGraph {
+    fn get(&self, point: Point3<f64>) -> f64 {
+        self.get_node(self.output, point)
+    }
+}
+
+/// Columns `[x, z]` read the graph at `[x, 0, z]`.
+impl NoiseFn<Point2<f64>> for NoiseGraph {
+    fn get(&self, [x, z]: Point2<f64>) -> f64 {
+        self.get_node(self.output, [x, 0.0, z])
+    }
+}
+
+/// Lets a node be the source of noise functions such as [`SubSampleNoise`].
+struct NodeRef<'a> {
+    graph: &'a NoiseGraph,
+    index: usize,
+}
+
+impl<'a> NoiseFn<Point3<f64>> for NodeRef<'a> {
+    fn get(&self, point: Point3<f64>) -> f64 {
+        self.graph.get_node(self.index, point)
+    }
+}
+
+fn map(array: &mut Array3x1<f64>, f: impl Fn(f64) -> f64) {
+    for pos in array.extent().iter_points() {
+        let value = array.get_mut(pos);
+        *value = f(*value);
+    }
+}
+
+/// Cubic interpolation through the control points around `value`,
+/// the nearest output is used past either end.
+fn curve(points: &[(f64, f64)], value: f64) -> f64 {
+    let last = points.len() - 1;
+    let next = points
+        .iter()
+        .position(|(input, _)| *input > value)
+        .unwrap_or(points.len())
+        .clamp(2, points.len());
+    let index0 = (next - 2).min(last);
+    let index1 = (next - 1).min(last);
+    let index2 = next.min(last);
+    let index3 = (next + 1).min(last);
+    if index1 == index2 {
+        return points[index1].1;
+    }
+
+    let (input1, input2) = (points[index1].0, points[index2].0);
+    let t = (value - input1) / (input2 - input1);
+    let (n0, n1, n2, n3) = (
+        points[index0].1,
+        points[index1].1,
+        points[index2].1,
+        points[index3].1,
+    );
+    let p = (n3 - n2) - (n0 - n1);
+    let q = (n0 - n1) - p;
+    let r = n2 - n0;
+    p * t * t * t + q * t * t + r * t + n1
+}
+
+fn select(
+    low: impl Fn() -> f64,
+    high: impl Fn() -> f64,
+    control: f64,
+    (lower, upper): (f64, f64),
+    falloff: f64,
+) -> f64 {
+    let s_curve = |edge: f64| {
+        let t = (control - (edge - falloff)) / (2.0 * falloff);
+        t * t * (3.0 - 2.0 * t)
+    };
+    if control < lower - falloff {
+        low()
+    } else if control < lower + falloff {
+        math::lerp(low(), high(), s_curve(lower))
+    } else if control < upper - falloff {
+        high()
+    } else if control < upper + falloff {
+        math::lerp(high(), low(), s_curve(upper))
+    } else {
+        low()
+    }
+}
+
+struct Builder<'a> {
+    desc: &'a NoiseGraphDesc,
+    seed: u32,
+    indices: HashMap<String, usize>,
+    /// Nodes being visited, to report cycles.
+    path: Vec<String>,
+    nodes: Vec<Node>,
+}
+
+impl<'a> Builder<'a> {
+    fn visit(&mut self, name: &str) -> Result<usize, NoiseGraphError> {
+        if let Some(index) = self.indices.get(name) {
+            return Ok(*index);
+        }
+        if let Some(start) = self.path.iter().position(|node| node == name) {
+            let mut cycle = self.path[start..].to_vec();
+            cycle.push(name.to_string());
+            return Err(NoiseGraphError::Cycle(cycle));
+        }
+
+        self.path.push(name.to_string());
+        let desc = self.desc;
+        let node = self.build(name, &desc.nodes[name])?;
+        self.path.pop();
+
+        self.nodes.push(node);
+        let index = self.nodes.len() - 1;
+        self.indices.insert(name.to_string(), index);
+        Ok(index)
+    }
+
+    /// Index of the node `name` reads as `source`.
+    fn source(&mut self, name: &str, source: &str) -> Result<usize, NoiseGraphError> {
+        if !self.desc.nodes.contains_key(source) {
+            return Err(NoiseGraphError::UnknownSource {
+                node: name.to_string(),
+                source: source.to_string(),
+            });
+        }
+        self.visit(source)
+    }
+
+    fn build(&mut self, name: &str, node: &NoiseNode) -> Result<Node, NoiseGraphError> {
+        let invalid = |reason: String| NoiseGraphError::Invalid {
+            node: name.to_string(),
+            reason,
+        };
+        Ok(match node {
+            NoiseNode::Fbm(fractal) => {
+                Node::Fbm(fractal.apply(Fbm::new(), self.seed).map_err(invalid)?)
+            }
+            NoiseNode::Ridged(fractal) => Node::Ridged(
+                fractal
+                    .apply(RidgedMulti::new(), self.seed)
+                    .map_err(invalid)?,
+            ),
+            NoiseNode::SuperSimplex { seed } => {
+                Node::SuperSimplex(SuperSimplex::new().set_seed(self.seed.wrapping_add(*seed)))
+            }
+            NoiseNode::Constant(value) => Node::Constant(*value),
+            NoiseNode::ScaleBias {
+                source,
+                scale,
+                bias,
+            } => Node::ScaleBias {
+                source: self.source(name, source)?,
+                scale: *scale,
+                bias: *bias,
+            },
+            NoiseNode::Add(sources) | NoiseNode::Multiply(sources) => {
+                if sources.is_empty() {
+                    return Err(invalid("it has no sources".to_string()));
+                }
+                let sources = sources
+                    .iter()
+                    .map(|source| self.source(name, source))
+                    .collect::<Result<_, _>>()?;
+                match node {
+                    NoiseNode::Add(_) => Node::Add(sources),
+                    _ => Node::Multiply(sources),
+                }
+            }
+            NoiseNode::Clamp { source, min, max } => {
+                if min > max {
+                    return Err(invalid(format!("min {} is above max {}", min, max)));
+                }
+                Node::Clamp {
+                    source: self.source(name, source)?,
+                    min: *min,
+                    max: *max,
+                }
+            }
+            NoiseNode::Curve { source, points } => {
+                if points.len() < 4 {
+                    return Err(invalid(format!(
+                        "a curve needs at least 4 points, {} given",
+                        points.len()
+                    )));
+                }
+                let mut points = points.clone();
+                points.sort_by(|a, b| a.0.partial_cmp(&b.0).unwrap_or(Ordering::Equal));
+                if let Some(pair) = points.windows(2).find(|pair| pair[0].0 == pair[1].0) {
+                    return Err(invalid(format!(
+                        "two points have the same input {}",
+                        pair[0].0
+                    )));
+                }
+                Node::Curve {
+                    source: self.source(name, source)?,
+                    points,
+                }
+            }
+            NoiseNode::Select {
+                low,
+                high,
+                control,
+                bounds,
+                falloff,
+            } => {
+                if bounds.0 > bounds.1 {
+                    return Err(invalid(format!(
+                        "lower bound {} is above upper bound {}",
+                        bounds.0, bounds.1
+                    )));
+                }
+                if *falloff < 0.0 {
+                    return Err(invalid(format!("falloff {} is negative", falloff)));
+                }
+                Node::Select {
+                    low: self.source(name, low)?,
+                    high: self.source(name, high)?,
+                    control: self.source(name, control)?,
+                    bounds: *bounds,
+                    falloff: *falloff,
+                }
+            }
+            NoiseNode::SubSample {
+                source,
+                scale,
+                sample_rate,
+            } => {
+                if *sample_rate < 1 {
+                    return Err(invalid(format!(
+                        "sample rate {} is not positive",
+                        sample_rate
+                    )));
+                }
+                Node::SubSample {
+                    source: self.source(name, source)?,
+                    scale: *scale,
+                    sample_rate: *sample_rate,
+                }
+            }
+        })
+    }
+}
+
+impl FractalDesc {
+    fn apply<T: MultiFractal + Seedable>(&self, mut noise: T, seed: u32) -> Result<T, String> {
+        if let Some(octaves) = self.octaves {
+            if !(1..=Fbm::MAX_OCTAVES).contains(&octaves) {
+                return Err(format!(
+                    "octaves {} is not between 1 and {}",
+                    octaves,
+                    Fbm::MAX_OCTAVES
+                ));
+            }
+            noise = noise.set_octaves(octaves);
+        }
+        if let Some(frequency) = self.frequency {
+            if !(frequency > 0.0 && frequency.is_finite()) {
+                return Err(format!("frequency {} is not positive", frequency));
+            }
+            noise = noise.set_frequency(frequency);
+        }
+        if let Some(lacunarity) = self.lacunarity {
+            noise = noise.set_lacunarity(lacunarity);
+        }
+        if let Some(persistence) = self.persistence {
+            noise = noise.set_persistence(persistence);
+        }
+        Ok(noise.set_seed(seed.wrapping_add(self.seed)))
+    }
+}
+
+/// Noise graphs of the world preset by name, facet providers read them through
+/// [`FacetContext::noise_graph`](super::FacetContext::noise_graph).
+#[derive(Default)]
+pub struct NoiseGraphs(HashMap<String, NoiseGraph>);
+
+impl NoiseGraphs {
+    pub fn get(&self, name: &str) -> Option<&NoiseGraph> {
+        self.0.get(name)
+    }
+}
+
+pub(super) fn load_noise_graphs(
+    preset: Res<WorldPreset>,
+    mut resources: ResMut<GeneratorResources>,
+) {
+    let mut graphs = NoiseGraphs::default();
+    for (name, path) in preset.noise.iter() {
+        match NoiseGraph::load(path, preset.seed as u32) {
+            Ok(graph) => {
+                info!("Loaded noise graph {} from {}", name, path);
+                graphs.0.insert(name.clone(), graph);
+            }
+            Err(e) => {
+                error!(
+                    "Noise graph {} cannot be loaded from {}, using the built-in noise instead {}",
+                    name, path, e
+                );
+            }
+        }
+    }
+    resources.insert(graphs);
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// The error the graph written as `ron` is rejected with.
+    fn error(ron: &str) -> NoiseGraphError {
+        let desc: NoiseGraphDesc = ron::from_str(ron).unwrap();
+        NoiseGraph::new(&desc, 0).err().expect("the graph is valid")
+    }
+
+    #[test]
+    fn valid_graph() {
+        let desc: NoiseGraphDesc = ron::from_str(
+            r#"(
+                nodes: {
+                    "base": Fbm(()),
+                    "scaled": ScaleBias(source: "base", scale: 2.0, bias: 1.0),
+                    "sum": Add(["scaled", "base"]),
+                },
+                output: "sum",
+            )"#,
+        )
+        .unwrap();
+        assert!(NoiseGraph::new(&desc, 0).is_ok());
+    }
+
+    #[test]
+    fn missing_output() {
+        match error(r#"(nodes: { "base": Constant(1.0) }, output: "density")"#) {
+            NoiseGraphError::MissingOutput(output) => assert_eq!(output, "density"),
+            e => panic!("unexpected error {:?}", e),
+        }
+    }
+
+    #[test]
+    fn unknown_source() {
+        match error(
+            r#"(
+                nodes: {
+                    "base": Constant(1.0),
+                    "sum": Add(["base", "missing"]),
+                },
+                output: "sum",
+            )"#,
+        ) {
+            NoiseGraphError::UnknownSource { node, source } => {
+                assert_eq!(node, "sum");
+                assert_eq!(source, "missing");
+            }
+            e => panic!("unexpected error {:?}", e),
+        }
+    }
+
+    #[test]
+    fn cycle() {
+        match error(
+            r#"(
+                nodes: {
+                    "a": ScaleBias(source: "b"),
+                    "b": Clamp(source: "a", min: 0.0, max: 1.0),
+                    "out": ScaleBias(source: "a"),
+                },
+                output: "out",
+            )"#,
+        ) {
+            NoiseGraphError::Cycle(cycle) => assert_eq!(cycle, ["a", "b", "a"]),
+            e => panic!("unexpected error {:?}", e),
+        }
+    }
+
+    #[test]
+    fn self_reference() {
+        match error(r#"(nodes: { "a": ScaleBias(source: "a") }, output: "a")"#) {
+            NoiseGraphError::Cycle(cycle) => assert_eq!(cycle, ["a", "a"]),
+            e => panic!("unexpected error {:?}", e),
+        }
+    }
+
+    #[test]
+    fn unread_nodes_are_checked() {
+        match error(
+            r#"(
+                nodes: {
+                    "out": Constant(1.0),
+                    "unused": ScaleBias(source: "missing"),
+                },
+                output: "out",
+            )"#,
+        ) {
+            NoiseGraphError::UnknownSource { node, .. } => assert_eq!(node, "unused"),
+            e => panic!("unexpected error {:?}", e),
+        }
+    }
+
+    #[test]
+    fn invalid_node() {
+        match error(
+            r#"(
+                nodes: {
+                    "base": Constant(1.0),
+                    "clamped": Clamp(source: "base", min: 1.0, max: 0.0),
+                },
+                output: "clamped",
+            )"#,
+        ) {
+            NoiseGraphError::Invalid { node, .. } => assert_eq!(node, "clamped"),
+            e => panic!("unexpected error {:?}", e),
+        }
+    }
+}
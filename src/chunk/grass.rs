@@ -0,0 +1,40 @@
+use bevy::prelude::*;
+use building_blocks::core::{Point3i, PointN};
+
+use crate::chunk::{BlockBehaviour, BlockBehaviourAppExt, BlockWorld};
+
+const UP: Point3i = PointN([0, 1, 0]);
+
+/// Spreads grass over the dirt around it, needs `BlockTickPlugin`.
+pub struct GrassPlugin;
+
+impl Plugin for GrassPlugin {
+    fn build(&self, app: &mut App) {
+        app.add_block_behaviour("grass", Spread);
+    }
+}
+
+/// Turns a random dirt block next to the grass, one block up or down at most, into grass when
+/// nothing solid is on top of it.
+pub struct Spread;
+
+impl BlockBehaviour for Spread {
+    fn random_tick(&self, world: &mut BlockWorld, pos: Point3i) {
+        let (grass, dirt) = match (world.voxel("grass"), world.voxel("dirt")) {
+            (Some(grass), Some(dirt)) => (grass, dirt),
+            _ => return,
+        };
+        let offset = PointN([
+            world.random(3) as i32 - 1,
+            world.random(3) as i32 - 1,
+            world.random(3) as i32 - 1,
+        ]);
+        let target = pos + offset;
+        let covered = world
+            .get(target + UP)
+            .map_or(true, |above| world.blocks().is_solid(above));
+        if world.get(target) == Some(dirt) && !covered {
+            world.set(target, grass);
+        }
+    }
+}
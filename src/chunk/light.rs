@@ -0,0 +1,353 @@
+use std::collections::{HashSet, VecDeque};
+
+use bevy::ecs::change_detection::DetectChanges;
+use bevy::prelude::*;
+use building_blocks::core::{Extent3i, Point3i, PointN};
+use building_blocks::prelude::{Get, GetMut};
+use building_blocks::storage::Array3x1;
+
+use crate::blocks::{Block, Blocks, VoxelBlocks};
+use crate::chunk::{chunk_extent, voxel_chunk, Chunk, ChunkWorld, Voxel, VoxelChanged};
+use crate::AppState;
+
+/// Light of the open sky and of the brightest blocks, each block it goes through takes one
+/// from it, but the sky light going down through air.
+pub const MAX_LIGHT: u8 = 15;
+
+/// Colour of the light given off by blocks, the sky light is white.
+const BLOCK_LIGHT_COLOR: [f32; 3] = [1.0, 0.85, 0.65];
+
+const NEIGHBOURS: [Point3i; 6] = [
+    PointN([1, 0, 0]),
+    PointN([-1, 0, 0]),
+    PointN([0, 1, 0]),
+    PointN([0, -1, 0]),
+    PointN([0, 0, 1]),
+    PointN([0, 0, -1]),
+];
+const UP: Point3i = PointN([0, 1, 0]);
+const DOWN: Point3i = PointN([0, -1, 0]);
+
+/// Light levels of a voxel, up to `MAX_LIGHT`.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Hash)]
+pub struct VoxelLight {
+    pub sky: u8,
+    /// Light coming from the blocks giving off light.
+    pub block: u8,
+}
+
+impl VoxelLight {
+    /// Colour the light of the blocks gives to what it lights, the sky light is dimmed by the
+    /// time of day when rendering so it is kept apart.
+    pub fn block_color(self) -> [f32; 3] {
+        let block = brightness(self.block);
+        BLOCK_LIGHT_COLOR.map(|channel| block * channel)
+    }
+}
+
+/// Light of the voxels of a chunk, next to its `Chunk`.
+#[derive(Clone, Component)]
+pub struct ChunkLight {
+    data: Array3x1<VoxelLight>,
+}
+
+impl ChunkLight {
+    fn new(extent: Extent3i) -> Self {
+        ChunkLight {
+            data: Array3x1::fill(extent, VoxelLight::default()),
+        }
+    }
+
+    /// Light at `pos`, in world coordinates inside the chunk.
+    pub fn get(&self, pos: Point3i) -> VoxelLight {
+        self.data.get(pos)
+    }
+
+    pub fn data(&self) -> &Array3x1<VoxelLight> {
+        &self.data
+    }
+}
+
+/// Brightness of a light level, from a dim ambient light in the dark up to one.
+pub fn brightness(light: u8) -> f32 {
+    const AMBIENT: f32 = 0.04;
+    let light = light as f32 / MAX_LIGHT as f32;
+    AMBIENT + (1.0 - AMBIENT) * light / (4.0 - 3.0 * light)
+}
+
+/// The light channels, flooded separately.
+#[derive(Clone, Copy, PartialEq)]
+enum Channel {
+    Sky,
+    Block,
+}
+
+/// Floods the sky light and the light of the blocks through the chunks, and keeps them up to
+/// date with their voxels.
+///
+/// The sky is above the highest loaded chunk of every column, and the missing chunks on the
+/// other sides are dark.
+pub struct LightPlugin;
+
+impl Plugin for LightPlugin {
+    fn build(&self, app: &mut App) {
+        app.init_resource::<UnlitChunks>().add_system_set(
+            SystemSet::on_update(AppState::Run)
+                .with_system(add_light.before("light"))
+                .with_system(update_light.label("light")),
+        );
+    }
+}
+
+/// Chunks whose `ChunkLight` was added but not computed yet.
+#[derive(Default)]
+struct UnlitChunks(Vec<Point3i>);
+
+/// Chunks are lit once their `ChunkLight` is added, a frame after they are in the world.
+fn add_light(
+    mut commands: Commands,
+    mut unlit: ResMut<UnlitChunks>,
+    chunks: Query<(Entity, &Chunk), Without<ChunkLight>>,
+) {
+    for (e, chunk) in chunks.iter() {
+        commands
+            .entity(e)
+            .insert(ChunkLight::new(chunk_extent(chunk.pos())));
+        unlit.0.push(chunk.pos());
+    }
+}
+
+fn update_light(
+    world: Res<ChunkWorld>,
+    blocks: Res<Blocks>,
+    block_assets: Res<Assets<Block>>,
+    mut changes: EventReader<VoxelChanged>,
+    mut unlit: ResMut<UnlitChunks>,
+    mut chunks: Query<(&Chunk, &mut ChunkLight)>,
+) {
+    // The light is inserted at the end of the frame, chunks which left the world are dropped.
+    let (new_chunks, waiting): (Vec<_>, Vec<_>) = std::mem::take(&mut unlit.0)
+        .into_iter()
+        .filter(|pos| world.world.contains_key(pos))
+        .partition(|pos| chunks.get(world.world[pos]).is_ok());
+    unlit.0 = waiting;
+    let changes: Vec<_> = changes.iter().map(|change| change.pos).collect();
+    if new_chunks.is_empty() && changes.is_empty() {
+        return;
+    }
+    let voxel_blocks = VoxelBlocks::new(&blocks, &block_assets);
+    let mut dirty = HashSet::new();
+    for channel in [Channel::Sky, Channel::Block] {
+        let mut engine = LightEngine {
+            channel,
+            world: &world,
+            chunks: &mut chunks,
+            blocks: &voxel_blocks,
+            add: VecDeque::new(),
+            remove: VecDeque::new(),
+            removed: Vec::new(),
+            dirty: &mut dirty,
+        };
+
+        // The sky the chunks below were lit by is behind the new chunks now.
+        if channel == Channel::Sky {
+            for pos in new_chunks.iter() {
+                let below = chunk_extent(*pos + DOWN);
+                let top = Extent3i::from_min_and_shape(
+                    PointN([below.minimum.x(), below.max().y(), below.minimum.z()]),
+                    PointN([below.shape.x(), 1, below.shape.z()]),
+                );
+                for pos in top.iter_points() {
+                    engine.darken(pos);
+                }
+            }
+        }
+        for pos in changes.iter() {
+            engine.darken(*pos);
+        }
+        engine.remove_light();
+
+        for pos in new_chunks.iter() {
+            let extent = chunk_extent(*pos);
+            let sources: Vec<_> = match channel {
+                Channel::Sky => extent
+                    .iter_points()
+                    .filter(|pos| pos.y() == extent.max().y())
+                    .collect(),
+                Channel::Block => extent.iter_points().collect(),
+            };
+            for pos in sources {
+                engine.add_source(pos);
+            }
+            // The light of the chunks around shines into the new one.
+            let padded = extent.padded(1);
+            for offset in NEIGHBOURS.iter() {
+                let neighbour = chunk_extent(*pos + *offset);
+                engine
+                    .add
+                    .extend(padded.intersection(&neighbour).iter_points());
+                engine.dirty.insert(*pos + *offset);
+            }
+        }
+        engine.add_light();
+    }
+
+    for pos in dirty {
+        if let Some((_, mut light)) = world.world.get(&pos).and_then(|e| chunks.get_mut(*e).ok()) {
+            light.set_changed();
+        }
+    }
+}
+
+/// Flood fill of the light over the loaded chunks, removing the light first and adding it
+/// back where it still comes from somewhere else.
+struct LightEngine<'a, 'w, 's> {
+    channel: Channel,
+    world: &'a ChunkWorld,
+    chunks: &'a mut Query<'w, 's, (&'static Chunk, &'static mut ChunkLight)>,
+    blocks: &'a VoxelBlocks,
+    add: VecDeque<Point3i>,
+    /// Darkened positions with the light they had.
+    remove: VecDeque<(Point3i, u8)>,
+    removed: Vec<Point3i>,
+    /// Chunks whose meshes show light which changed.
+    dirty: &'a mut HashSet<Point3i>,
+}
+
+impl<'a, 'w, 's> LightEngine<'a, 'w, 's> {
+    fn voxel(&self, pos: Point3i) -> Option<Voxel> {
+        let (chunk, _) = self
+            .chunks
+            .get(*self.world.world.get(&voxel_chunk(pos))?)
+            .ok()?;
+        Some(chunk.data().get(pos))
+    }
+
+    fn light(&self, pos: Point3i) -> Option<u8> {
+        let (_, light) = self
+            .chunks
+            .get(*self.world.world.get(&voxel_chunk(pos))?)
+            .ok()?;
+        let light = light.get(pos);
+        Some(match self.channel {
+            Channel::Sky => light.sky,
+            Channel::Block => light.block,
+        })
+    }
+
+    fn set_light(&mut self, pos: Point3i, value: u8) {
+        let chunk_pos = voxel_chunk(pos);
+        let light = self
+            .world
+            .world
+            .get(&chunk_pos)
+            .and_then(|e| self.chunks.get_mut(*e).ok());
+        if let Some((_, mut light)) = light {
+            let light = light.data.get_mut(pos);
+            match self.channel {
+                Channel::Sky => light.sky = value,
+                Channel::Block => light.block = value,
+            }
+            // Faces of the chunks around may be lit by it.
+            for offset in NEIGHBOURS.iter() {
+                self.dirty.insert(voxel_chunk(pos + *offset));
+            }
+        }
+    }
+
+    /// Light going from a voxel lit by `light` into `voxel`, next to it by `offset`.
+    fn propagated(&self, light: u8, offset: Point3i, voxel: Voxel) -> u8 {
+        if self.blocks.is_solid(voxel) {
+            0
+        } else if self.channel == Channel::Sky
+            && offset == DOWN
+            && light == MAX_LIGHT
+            && voxel == Voxel::AIR
+        {
+            MAX_LIGHT
+        } else {
+            light.saturating_sub(1)
+        }
+    }
+
+    /// Light at `pos` which does not come from the voxels around: the sky above the loaded
+    /// chunks or the light the block gives off.
+    fn source(&self, pos: Point3i) -> u8 {
+        let voxel = match self.voxel(pos) {
+            Some(voxel) => voxel,
+            None => return 0,
+        };
+        match self.channel {
+            Channel::Sky if self.world.world.contains_key(&voxel_chunk(pos + UP)) => 0,
+            Channel::Sky => self.propagated(MAX_LIGHT, DOWN, voxel),
+            Channel::Block => self
+                .blocks
+                .get(voxel)
+                .map_or(0, |block| block.light_emission.min(MAX_LIGHT)),
+        }
+    }
+
+    fn add_source(&mut self, pos: Point3i) {
+        let source = self.source(pos);
+        if source > self.light(pos).unwrap_or(MAX_LIGHT) {
+            self.set_light(pos, source);
+            self.add.push_back(pos);
+        }
+    }
+
+    /// Removes the light at `pos` and what it lit, with `remove_light`.
+    fn darken(&mut self, pos: Point3i) {
+        if let Some(light) = self.light(pos) {
+            self.set_light(pos, 0);
+            self.remove.push_back((pos, light));
+            self.removed.push(pos);
+        }
+    }
+
+    fn remove_light(&mut self) {
+        while let Some((pos, light)) = self.remove.pop_front() {
+            for offset in NEIGHBOURS.iter() {
+                let neighbour = pos + *offset;
+                let (voxel, neighbour_light) = match (self.voxel(neighbour), self.light(neighbour))
+                {
+                    (Some(voxel), Some(light)) if light > 0 => (voxel, light),
+                    _ => continue,
+                };
+                // Lit by `pos` or as bright as if it was, otherwise it lights the darkened
+                // voxels back.
+                if neighbour_light <= self.propagated(light, *offset, voxel) {
+                    self.set_light(neighbour, 0);
+                    self.remove.push_back((neighbour, neighbour_light));
+                    self.removed.push(neighbour);
+                } else {
+                    self.add.push_back(neighbour);
+                }
+            }
+        }
+        for pos in std::mem::take(&mut self.removed) {
+            self.add_source(pos);
+        }
+    }
+
+    fn add_light(&mut self) {
+        while let Some(pos) = self.add.pop_front() {
+            let light = match self.light(pos) {
+                Some(light) if light > 0 => light,
+                _ => continue,
+            };
+            for offset in NEIGHBOURS.iter() {
+                let neighbour = pos + *offset;
+                let (voxel, neighbour_light) = match (self.voxel(neighbour), self.light(neighbour))
+                {
+                    (Some(voxel), Some(light)) => (voxel, light),
+                    _ => continue,
+                };
+                let light = self.propagated(light, *offset, voxel);
+                if light > neighbour_light {
+                    self.set_light(neighbour, light);
+                    self.add.push_back(neighbour);
+                }
+            }
+        }
+    }
+}
@@ -0,0 +1,88 @@
+use bevy::prelude::*;
+use building_blocks::core::{Point3i, PointN};
+
+use crate::blocks::VoxelBlocks;
+use crate::chunk::{BlockBehaviour, BlockBehaviourAppExt, BlockWorld, Voxel};
+
+/// Ticks between a change next to a liquid and the liquid flowing.
+const FLOW_DELAY: u64 = 4;
+
+const HORIZONTAL: [Point3i; 4] = [
+    PointN([1, 0, 0]),
+    PointN([-1, 0, 0]),
+    PointN([0, 0, 1]),
+    PointN([0, 0, -1]),
+];
+const UP: Point3i = PointN([0, 1, 0]);
+const DOWN: Point3i = PointN([0, -1, 0]);
+
+/// Spreads water around the voxels that change, needs `BlockTickPlugin`.
+pub struct LiquidPlugin;
+
+impl Plugin for LiquidPlugin {
+    fn build(&self, app: &mut App) {
+        app.add_block_behaviour("water", Flow);
+    }
+}
+
+/// Makes a liquid flow into the voxels below and beside it, or dry out.
+pub struct Flow;
+
+impl BlockBehaviour for Flow {
+    fn neighbour_changed(&self, world: &mut BlockWorld, pos: Point3i) {
+        world.schedule(pos, FLOW_DELAY, 0);
+    }
+
+    fn scheduled_tick(&self, world: &mut BlockWorld, pos: Point3i) {
+        // Every position is decided from the voxels before this tick, so the order they are
+        // in does not matter.
+        let mut positions = vec![pos, pos + DOWN];
+        positions.extend(HORIZONTAL.iter().map(|offset| pos + *offset));
+        let get = |pos: Point3i| world.get(pos);
+        let flows: Vec<_> = positions
+            .into_iter()
+            .filter_map(|pos| Some((pos, next_voxel(pos, &get, world.blocks())?)))
+            .collect();
+        for (pos, voxel) in flows {
+            world.set(pos, voxel);
+        }
+    }
+}
+
+/// What the voxel at `pos` turns into, `None` if it stays as it is.
+///
+/// Liquids fall first, a flowing liquid which can fall does not spread sideways. Each block
+/// sideways raises the level by one until the `spread` of the liquid, sources never change.
+fn next_voxel(
+    pos: Point3i,
+    get: &impl Fn(Point3i) -> Option<Voxel>,
+    blocks: &VoxelBlocks,
+) -> Option<Voxel> {
+    let current = get(pos)?;
+    let is_liquid = |voxel: Voxel| blocks.fluid(voxel).is_some();
+    // Only air and flowing liquids are replaced.
+    if current != Voxel::AIR && !(is_liquid(current) && current.level() > 0) {
+        return None;
+    }
+
+    let next = match get(pos + UP).filter(|above| is_liquid(*above)) {
+        // Falling liquids keep the highest flowing level.
+        Some(above) => Voxel::new(above.block(), 1),
+        None => HORIZONTAL
+            .iter()
+            .filter_map(|offset| {
+                let neighbour = get(pos + *offset)?;
+                let fluid = blocks.fluid(neighbour)?;
+                let falls = get(pos + *offset + DOWN).map_or(false, |below| {
+                    below == Voxel::AIR || (is_liquid(below) && below.level() > 0)
+                });
+                let level = neighbour.level() + 1;
+                let spreads = neighbour.level() == 0 || !falls;
+                (spreads && level <= fluid.spread.min(Voxel::MAX_LEVEL))
+                    .then(|| Voxel::new(neighbour.block(), level))
+            })
+            .min_by_key(|voxel| voxel.level())
+            .unwrap_or(Voxel::AIR),
+    };
+    (next != current).then(|| next)
+}
@@ -0,0 +1,194 @@
+use bevy::ecs::system::lifetimeless::SRes;
+use bevy::ecs::system::SystemParamItem;
+use bevy::pbr::{MaterialPipeline, SpecializedMaterial};
+use bevy::prelude::*;
+use bevy::reflect::TypeUuid;
+use bevy::render::render_asset::{PrepareAssetError, RenderAsset, RenderAssets};
+use bevy::render::render_resource::std140::{AsStd140, Std140};
+use bevy::render::render_resource::{
+    BindGroup, BindGroupDescriptor, BindGroupEntry, BindGroupLayout, BindGroupLayoutDescriptor,
+    BindGroupLayoutEntry, BindingResource, BindingType, Buffer, BufferBindingType,
+    BufferInitDescriptor, BufferSize, BufferUsages, RenderPipelineDescriptor, SamplerBindingType,
+    ShaderStages, TextureSampleType, TextureViewDimension, VertexAttribute, VertexBufferLayout,
+    VertexFormat, VertexStepMode,
+};
+use bevy::render::renderer::RenderDevice;
+
+/// Textured chunk meshes lit by their vertex colours only.
+#[derive(Debug, Clone, TypeUuid)]
+#[uuid = "3c5d8f0e-6a0b-4a53-9a4e-2f1c7d9b0e61"]
+pub struct ChunkMaterial {
+    pub texture: Handle<Image>,
+    /// Part of the sky light reaching the world, one by day.
+    pub daylight: f32,
+    /// Colour the meshes fade into from `fog_start` to `fog_end` away from the camera.
+    pub fog_color: Color,
+    pub fog_start: f32,
+    pub fog_end: f32,
+}
+
+impl ChunkMaterial {
+    /// Material lit by day without fog, until they are set.
+    pub fn new(texture: Handle<Image>) -> Self {
+        ChunkMaterial {
+            texture,
+            daylight: 1.0,
+            fog_color: Color::NONE,
+            fog_start: f32::MAX,
+            fog_end: f32::MAX,
+        }
+    }
+
+    /// How much of the ambient light reaches each vertex, from zero to one.
+    pub const ATTRIBUTE_AMBIENT_OCCLUSION: &'static str = "Vertex_Ao";
+}
+
+#[derive(Clone, AsStd140)]
+struct ChunkMaterialUniformData {
+    fog_color: Vec4,
+    daylight: f32,
+    fog_start: f32,
+    fog_end: f32,
+}
+
+#[derive(Clone)]
+pub struct GpuChunkMaterial {
+    _buffer: Buffer,
+    bind_group: BindGroup,
+}
+
+impl RenderAsset for ChunkMaterial {
+    type ExtractedAsset = ChunkMaterial;
+    type PreparedAsset = GpuChunkMaterial;
+    type Param = (
+        SRes<RenderDevice>,
+        SRes<MaterialPipeline<Self>>,
+        SRes<RenderAssets<Image>>,
+    );
+
+    fn extract_asset(&self) -> Self::ExtractedAsset {
+        self.clone()
+    }
+
+    fn prepare_asset(
+        material: Self::ExtractedAsset,
+        (render_device, pipeline, images): &mut SystemParamItem<Self::Param>,
+    ) -> Result<Self::PreparedAsset, PrepareAssetError<Self::ExtractedAsset>> {
+        let image = match images.get(&material.texture) {
+            Some(image) => image,
+            None => return Err(PrepareAssetError::RetryNextUpdate(material)),
+        };
+        let value = ChunkMaterialUniformData {
+            fog_color: material.fog_color.as_linear_rgba_f32().into(),
+            daylight: material.daylight,
+            fog_start: material.fog_start,
+            fog_end: material.fog_end,
+        };
+        let buffer = render_device.create_buffer_with_data(&BufferInitDescriptor {
+            label: Some("chunk_material_uniform_buffer"),
+            usage: BufferUsages::UNIFORM | BufferUsages::COPY_DST,
+            contents: value.as_std140().as_bytes(),
+        });
+        let bind_group = render_device.create_bind_group(&BindGroupDescriptor {
+            entries: &[
+                BindGroupEntry {
+                    binding: 0,
+                    resource: BindingResource::TextureView(&image.texture_view),
+                },
+                BindGroupEntry {
+                    binding: 1,
+                    resource: BindingResource::Sampler(&image.sampler),
+                },
+                BindGroupEntry {
+                    binding: 2,
+                    resource: buffer.as_entire_binding(),
+                },
+            ],
+            label: Some("chunk_material_bind_group"),
+            layout: &pipeline.material_layout,
+        });
+        Ok(GpuChunkMaterial {
+            _buffer: buffer,
+            bind_group,
+        })
+    }
+}
+
+impl SpecializedMaterial for ChunkMaterial {
+    type Key = ();
+
+    fn key(_material: &<Self as RenderAsset>::PreparedAsset) -> Self::Key {}
+
+    /// Adds the colours and the ambient occlusion to the vertex buffer, whose attributes are sorted by name.
+    fn specialize(_key: Self::Key, descriptor: &mut RenderPipelineDescriptor) {
+        let attribute = |shader_location, offset, format| VertexAttribute {
+            format,
+            offset,
+            shader_location,
+        };
+        descriptor.vertex.buffers = vec![VertexBufferLayout {
+            array_stride: 52,
+            step_mode: VertexStepMode::Vertex,
+            attributes: vec![
+                // Vertex_Ao
+                attribute(4, 0, VertexFormat::Float32),
+                // Vertex_Color
+                attribute(3, 4, VertexFormat::Float32x4),
+                // Vertex_Normal
+                attribute(1, 20, VertexFormat::Float32x3),
+                // Vertex_Position
+                attribute(0, 32, VertexFormat::Float32x3),
+                // Vertex_Uv
+                attribute(2, 44, VertexFormat::Float32x2),
+            ],
+        }];
+    }
+
+    fn bind_group(material: &<Self as RenderAsset>::PreparedAsset) -> &BindGroup {
+        &material.bind_group
+    }
+
+    fn bind_group_layout(render_device: &RenderDevice) -> BindGroupLayout {
+        render_device.create_bind_group_layout(&BindGroupLayoutDescriptor {
+            entries: &[
+                BindGroupLayoutEntry {
+                    binding: 0,
+                    visibility: ShaderStages::FRAGMENT,
+                    ty: BindingType::Texture {
+                        multisampled: false,
+                        sample_type: TextureSampleType::Float { filterable: true },
+                        view_dimension: TextureViewDimension::D2,
+                    },
+                    count: None,
+                },
+                BindGroupLayoutEntry {
+                    binding: 1,
+                    visibility: ShaderStages::FRAGMENT,
+                    ty: BindingType::Sampler(SamplerBindingType::Filtering),
+                    count: None,
+                },
+                BindGroupLayoutEntry {
+                    binding: 2,
+                    visibility: ShaderStages::FRAGMENT,
+                    ty: BindingType::Buffer {
+                        ty: BufferBindingType::Uniform,
+                        has_dynamic_offset: false,
+                        min_binding_size: BufferSize::new(
+                            ChunkMaterialUniformData::std140_size_static() as u64,
+                        ),
+                    },
+                    count: None,
+                },
+            ],
+            label: Some("chunk_material_layout"),
+        })
+    }
+
+    fn vertex_shader(asset_server: &AssetServer) -> Option<Handle<Shader>> {
+        Some(asset_server.load("shaders/chunk.wgsl"))
+    }
+
+    fn fragment_shader(asset_server: &AssetServer) -> Option<Handle<Shader>> {
+        Some(asset_server.load("shaders/chunk.wgsl"))
+    }
+}
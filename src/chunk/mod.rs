@@ -16,14 +16,40 @@ use building_blocks::prelude::*;
 use building_blocks::storage::{Array, Channel};
 use futures_lite::future;
 
-pub use generation::ChunkGeneratorPlugin;
+pub use falling::{FallingBlock, FallingBlockPlugin, Falls, RemoteFallingBlock};
+pub use generation::{
+    generate_area, ChunkGeneratorPlugin, ColumnCache, DensityFacet, ElevationFacet, Erosion,
+    ErosionSettings, Facet2D, Facet3D, FacetAppExt, FacetContext, FacetGraphError, FacetId,
+    FacetProvider, FacetRegistry, Facets, FractalDesc, GeneratingArea, GeneratorResources,
+    GeneratorSettings, Heightmap, HeightmapSettings, NoiseGraph, NoiseGraphDesc, NoiseGraphError,
+    NoiseGraphs, NoiseNode, SeaLevel, Seed, SubSampleNoise, SurfaceFacet, SurfaceRoughnessFacet,
+    WaterLevelFacet, WorldPreset, COLUMN_CACHE_HITS, COLUMN_CACHE_MISSES,
+};
+pub use grass::{GrassPlugin, Spread};
+pub use light::{brightness, ChunkLight, LightPlugin, VoxelLight, MAX_LIGHT};
+pub use liquid::{Flow, LiquidPlugin};
+pub use material::ChunkMaterial;
 use rendering::UV_SCALE;
+pub use rendering::{ChunkRenderPlugin, ChunkRenderSettings};
+pub use ticks::{
+    BlockBehaviour, BlockBehaviourAppExt, BlockBehaviours, BlockTickPlugin, BlockTicks, BlockWorld,
+    TickSettings,
+};
 
 use crate::blocks::{Block, BlockId, Blocks};
 use crate::{AppState, LoadState};
 
+mod falling;
 mod generation;
+mod grass;
+mod light;
+mod liquid;
+mod material;
 mod rendering;
+mod ticks;
+
+/// Width of the chunks along each axis, in voxels.
+pub const CHUNK_SIZE: i32 = 32;
 
 pub struct ChunkPlugin;
 
@@ -31,12 +57,9 @@ impl Plugin for ChunkPlugin {
     fn build(&self, app: &mut bevy::prelude::App) {
         app.insert_resource(ChunkWorld::default())
             .add_event::<ChunkEvent>()
+            .add_event::<VoxelChanged>()
             .add_system_set(
                 SystemSet::on_update(AppState::Run)
-                    .with_system(rendering::build_mesh)
-                    .with_system(rendering::update_chunk)
-                    .with_system(rendering::build_mesh_done)
-                    .with_system(generation::generate_chunk)
                     .with_system(remove_from_world)
                     .with_system(add_to_world)
                     .with_system(relative_update)
@@ -81,6 +104,12 @@ pub fn remove_from_world(mut world: ResMut<ChunkWorld>, removals: RemovedCompone
     }
 }
 
+/// Voxel of a chunk which was replaced, sent by whoever replaced it.
+pub struct VoxelChanged {
+    pub pos: Point3i,
+    pub voxel: Voxel,
+}
+
 pub enum ChunkEvent {
     Generate(ChunkLocation),
     Update(ChunkLocation),
@@ -97,9 +126,9 @@ pub fn chunk_location_add(
     for (e, t) in query.iter() {
         let [x, y, z] = t.translation.to_array();
         commands.entity(e).insert(ChunkLocation(PointN([
-            x as i32 / 32,
-            y as i32 / 32,
-            z as i32 / 32,
+            x as i32 / CHUNK_SIZE,
+            y as i32 / CHUNK_SIZE,
+            z as i32 / CHUNK_SIZE,
         ])));
     }
 }
@@ -109,7 +138,11 @@ pub fn chunk_location_transition(
 ) {
     for (mut chunk_location, &transform) in query.iter_mut() {
         let [x, y, z] = transform.translation.to_array();
-        let pos = PointN([x as i32 / 32, y as i32 / 32, z as i32 / 32]);
+        let pos = PointN([
+            x as i32 / CHUNK_SIZE,
+            y as i32 / CHUNK_SIZE,
+            z as i32 / CHUNK_SIZE,
+        ]);
         if pos != chunk_location.0 {
             chunk_location.0 = pos
         }
@@ -144,8 +177,35 @@ fn chunk_location<T: Into<[i32; 3]>>(pos: T) -> ChunkLocation {
 }
 
 /// Basic voxel type with one byte of texture layers
-#[derive(Default, Clone, Copy)]
-pub struct Voxel(u8);
+///
+/// The low five bits are the block id plus one, zero being air. The high three bits are the
+/// level of flowing liquids, zero for sources and any other block.
+#[derive(Default, Clone, Copy, Debug, PartialEq, Eq)]
+pub struct Voxel(pub u8);
+
+impl Voxel {
+    pub const AIR: Voxel = Voxel(0);
+    /// Level of the flowing liquids furthest from their source.
+    pub const MAX_LEVEL: u8 = 7;
+
+    pub fn new(block: u8, level: u8) -> Self {
+        debug_assert!(block < 32 && level <= Voxel::MAX_LEVEL);
+        Voxel(block | (level << 5))
+    }
+
+    /// Block id plus one, zero for air.
+    pub fn block(self) -> u8 {
+        self.0 & 0b1_1111
+    }
+
+    pub fn block_id(self) -> Option<BlockId> {
+        self.block().checked_sub(1).map(BlockId::from)
+    }
+
+    pub fn level(self) -> u8 {
+        self.0 >> 5
+    }
+}
 
 impl MergeVoxel for Voxel {
     type VoxelValue = u8;
@@ -163,7 +223,7 @@ impl IsOpaque for Voxel {
 
 impl IsEmpty for Voxel {
     fn is_empty(&self) -> bool {
-        self.0 == 0
+        self.block() == 0
     }
 }
 
@@ -177,27 +237,136 @@ pub struct BlockMesh {
     pub positions: Vec<[f32; 3]>,
     pub normals: Vec<[f32; 3]>,
     pub tex_coords: Vec<[f32; 2]>,
+    pub colors: Vec<[f32; 4]>,
+    pub ambient_occlusion: Vec<f32>,
     pub indices: Vec<u32>,
 }
 
+/// How a face of a voxel is lit, the same on every voxel merged into a quad.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Hash)]
+pub struct FaceShading {
+    /// Light of the voxel in front of the face.
+    pub light: VoxelLight,
+    /// Neighbours around each corner hiding it from the ambient light, from 3 for the darkest
+    /// to 0, in the order of the corners of the quads.
+    pub occlusion: [u8; 4],
+}
+
+/// Ambient light reaching a corner hidden by 0 to 3 neighbours.
+const AMBIENT_OCCLUSION: [f32; 4] = [1.0, 0.82, 0.66, 0.5];
+
+/// Vertex colour of a face lit by `light`, the faces which do not look up are darker so the
+/// shape of the blocks shows.
+///
+/// The colour of the block light goes in the RGB channels and the brightness of the sky light
+/// in the alpha channel, the chunk shader lights the face by the brighter of both.
+fn face_color(normal: [f32; 3], light: VoxelLight) -> [f32; 4] {
+    let shade = match normal {
+        [_, y, _] if y > 0.0 => 1.0,
+        [_, y, _] if y < 0.0 => 0.5,
+        [x, _, _] if x != 0.0 => 0.8,
+        _ => 0.65,
+    };
+    let [r, g, b] = light.block_color();
+    [
+        r * shade,
+        g * shade,
+        b * shade,
+        brightness(light.sky) * shade,
+    ]
+}
+
+/// Normals and corners of the faces of a block, counter-clockwise seen from outside.
+const BLOCK_FACES: [([f32; 3], [[f32; 3]; 4]); 6] = [
+    (
+        [1.0, 0.0, 0.0],
+        [
+            [1.0, 0.0, 0.0],
+            [1.0, 1.0, 0.0],
+            [1.0, 1.0, 1.0],
+            [1.0, 0.0, 1.0],
+        ],
+    ),
+    (
+        [-1.0, 0.0, 0.0],
+        [
+            [0.0, 0.0, 0.0],
+            [0.0, 0.0, 1.0],
+            [0.0, 1.0, 1.0],
+            [0.0, 1.0, 0.0],
+        ],
+    ),
+    (
+        [0.0, 1.0, 0.0],
+        [
+            [0.0, 1.0, 0.0],
+            [0.0, 1.0, 1.0],
+            [1.0, 1.0, 1.0],
+            [1.0, 1.0, 0.0],
+        ],
+    ),
+    (
+        [0.0, -1.0, 0.0],
+        [
+            [0.0, 0.0, 0.0],
+            [1.0, 0.0, 0.0],
+            [1.0, 0.0, 1.0],
+            [0.0, 0.0, 1.0],
+        ],
+    ),
+    (
+        [0.0, 0.0, 1.0],
+        [
+            [0.0, 0.0, 1.0],
+            [1.0, 0.0, 1.0],
+            [1.0, 1.0, 1.0],
+            [0.0, 1.0, 1.0],
+        ],
+    ),
+    (
+        [0.0, 0.0, -1.0],
+        [
+            [0.0, 0.0, 0.0],
+            [0.0, 1.0, 0.0],
+            [1.0, 1.0, 0.0],
+            [1.0, 0.0, 0.0],
+        ],
+    ),
+];
+
 impl MeshBuf {
+    /// Adds a quad of voxels shaded by `shading`, `origin` being the minimum of the chunk.
     fn add_quad(
         &mut self,
         face: &OrientedCubeFace,
         quad: &UnorientedQuad,
         u_flip_face: Axis3,
         block_id: BlockId,
+        origin: Point3i,
+        shading: FaceShading,
     ) {
         let voxel_size = 1.0;
         let block_mesh = self.data.entry(block_id).or_insert(BlockMesh::default());
 
         let start_index = block_mesh.positions.len() as u32;
+        let mut positions = face.quad_mesh_positions(quad, voxel_size);
+        for position in positions.iter_mut() {
+            for (c, origin) in position.iter_mut().zip(origin.0) {
+                *c -= origin as f32;
+            }
+        }
+        block_mesh.positions.extend_from_slice(&positions);
+        let normals = face.quad_mesh_normals();
         block_mesh
-            .positions
-            .extend_from_slice(&face.quad_mesh_positions(quad, voxel_size));
-        block_mesh
-            .normals
-            .extend_from_slice(&face.quad_mesh_normals());
+            .colors
+            .extend_from_slice(&[face_color(normals[0], shading.light); 4]);
+        block_mesh.normals.extend_from_slice(&normals);
+        let occlusion = shading.occlusion;
+        block_mesh.ambient_occlusion.extend(
+            occlusion
+                .iter()
+                .map(|hidden| AMBIENT_OCCLUSION[*hidden as usize]),
+        );
         let flip_v = true;
         let mut uvs = face.tex_coords(u_flip_face, flip_v, quad);
         for uv in uvs.iter_mut() {
@@ -206,9 +375,63 @@ impl MeshBuf {
             }
         }
         block_mesh.tex_coords.extend_from_slice(&uvs);
-        block_mesh
-            .indices
-            .extend_from_slice(&face.quad_mesh_indices(start_index));
+        let mut indices = face.quad_mesh_indices(start_index);
+        // Split along the diagonal between the brightest corners, so that a dark corner only
+        // darkens its own triangle.
+        if occlusion[0] + occlusion[3] < occlusion[1] + occlusion[2] {
+            let i = start_index;
+            let counter_clockwise = indices[1] == i + 1;
+            indices = if counter_clockwise {
+                [i, i + 1, i + 3, i, i + 3, i + 2]
+            } else {
+                [i, i + 3, i + 1, i, i + 2, i + 3]
+            };
+        }
+        block_mesh.indices.extend_from_slice(&indices);
+    }
+
+    /// Adds the faces of a block `height` high at `pos` in the chunk, `visible` tells which
+    /// of `BLOCK_FACES` are drawn and `lights` how they are lit.
+    fn add_block_faces(
+        &mut self,
+        pos: [f32; 3],
+        height: f32,
+        visible: [bool; 6],
+        lights: [VoxelLight; 6],
+        block_id: BlockId,
+    ) {
+        let block_mesh = self.data.entry(block_id).or_insert(BlockMesh::default());
+        let faces = BLOCK_FACES.iter().zip(visible).zip(lights);
+        for (((normal, corners), _), light) in faces.filter(|((_, v), _)| *v) {
+            let start_index = block_mesh.positions.len() as u32;
+            for corner in corners {
+                let position = [
+                    pos[0] + corner[0],
+                    pos[1] + corner[1] * height,
+                    pos[2] + corner[2],
+                ];
+                // Textures are projected along the normal, like on the greedy quads.
+                let axis = normal.iter().position(|n| *n != 0.0).unwrap();
+                let (u, v) = match axis {
+                    0 => (position[2], position[1]),
+                    1 => (position[0], position[2]),
+                    _ => (position[0], position[1]),
+                };
+                block_mesh.positions.push(position);
+                block_mesh.normals.push(*normal);
+                block_mesh.tex_coords.push([u * UV_SCALE, v * UV_SCALE]);
+                block_mesh.colors.push(face_color(*normal, light));
+                block_mesh.ambient_occlusion.push(AMBIENT_OCCLUSION[0]);
+            }
+            block_mesh.indices.extend_from_slice(&[
+                start_index,
+                start_index + 1,
+                start_index + 2,
+                start_index,
+                start_index + 2,
+                start_index + 3,
+            ]);
+        }
     }
 }
 
@@ -218,9 +441,47 @@ pub struct Chunk {
     data: Array<[i32; 3], Channel<Voxel>>,
 }
 
+impl Chunk {
+    pub fn new(pos: Point3i, data: Array3x1<Voxel>) -> Self {
+        Chunk { pos, data }
+    }
+
+    pub fn pos(&self) -> Point3i {
+        self.pos
+    }
+
+    pub fn data(&self) -> &Array3x1<Voxel> {
+        &self.data
+    }
+
+    pub fn data_mut(&mut self) -> &mut Array3x1<Voxel> {
+        &mut self.data
+    }
+}
+
+/// Voxels covered by the chunk at `pos`.
+pub fn chunk_extent(pos: Point3i) -> Extent3i {
+    Extent3i::from_min_and_shape(pos * PointN([CHUNK_SIZE; 3]), PointN([CHUNK_SIZE; 3]))
+}
+
+/// Position of the chunk containing the voxel at `pos`.
+pub fn voxel_chunk(pos: Point3i) -> Point3i {
+    PointN([
+        pos.x().div_euclid(CHUNK_SIZE),
+        pos.y().div_euclid(CHUNK_SIZE),
+        pos.z().div_euclid(CHUNK_SIZE),
+    ])
+}
+
+/// Voxel at `pos`, `None` when its chunk is not generated.
+pub fn get_voxel(world: &ChunkWorld, chunks: &Query<&Chunk>, pos: Point3i) -> Option<Voxel> {
+    let chunk = chunks.get(*world.world.get(&voxel_chunk(pos))?).ok()?;
+    Some(chunk.data().get(pos))
+}
+
 impl Default for Chunk {
     fn default() -> Self {
-        let extent = Extent3i::from_min_and_shape(PointN::default(), PointN([32; 3]));
+        let extent = chunk_extent(Point3i::zero());
         let voxels = Array3x1::fill(extent, Voxel::default());
         Chunk {
             pos: Point3i::zero(),
@@ -230,7 +491,7 @@ impl Default for Chunk {
 }
 
 #[derive(Component)]
-pub struct ChunkLocation(Point3i);
+pub struct ChunkLocation(pub Point3i);
 
 impl From<IVec3> for ChunkLocation {
     fn from(vec: IVec3) -> Self {
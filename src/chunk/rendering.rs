@@ -1,55 +1,140 @@
+use std::collections::HashMap;
+
+use bevy::pbr::{MaterialMeshBundle, MaterialPlugin};
 use bevy::render::mesh::Indices;
 use bevy::render::render_resource::PrimitiveTopology;
 use bevy::tasks::{ComputeTaskPool, Task};
+use building_blocks::core::{Extent3i, Point3i, PointN};
 use building_blocks::mesh::{
-    greedy_quads, padded_greedy_quads_chunk_extent, GreedyQuadsBuffer, RIGHT_HANDED_Y_UP_CONFIG,
+    greedy_quads, padded_greedy_quads_chunk_extent, GreedyQuadsBuffer, IsOpaque, MergeVoxel,
+    OrientedCubeFace, RIGHT_HANDED_Y_UP_CONFIG,
 };
-use building_blocks::prelude::{copy_extent, Get};
+use building_blocks::prelude::{copy_extent, Get, GetMut, IsEmpty};
 use building_blocks::storage::Array3x1;
 use futures_lite::future;
 
-use crate::blocks::{Block, Blocks};
-use crate::chunk::{BlockMesh, Chunk, MeshBuf, Voxel};
+use crate::blocks::{Block, BlockId, Blocks, VoxelBlocks};
+use crate::chunk::{
+    chunk_extent, BlockMesh, Chunk, ChunkLight, ChunkMaterial, ChunkWorld, FaceShading, MeshBuf,
+    Voxel, VoxelLight, BLOCK_FACES, MAX_LIGHT,
+};
 use crate::{
-    AssetServer, Assets, BuildChildren, Changed, Commands, Entity, Mesh, PbrBundle, Query, Res,
-    ResMut, StandardMaterial, Transform,
+    App, AppState, AssetServer, Assets, BuildChildren, Changed, Children, Commands,
+    DespawnRecursiveExt, Entity, Handle, Local, Mesh, Or, ParallelSystemDescriptorCoercion, Plugin,
+    Query, Res, ResMut, SystemSet, Transform,
 };
 
 pub const UV_SCALE: f32 = 0.1;
 
+/// How the chunks are meshed, they are all meshed again when it changes.
+pub struct ChunkRenderSettings {
+    /// Darkens the corners of the faces next to other blocks.
+    pub ambient_occlusion: bool,
+}
+
+impl Default for ChunkRenderSettings {
+    fn default() -> Self {
+        ChunkRenderSettings {
+            ambient_occlusion: true,
+        }
+    }
+}
+
+/// Meshes chunks once they are lit, left out of headless apps and needs `LightPlugin`.
+pub struct ChunkRenderPlugin;
+
+impl Plugin for ChunkRenderPlugin {
+    fn build(&self, app: &mut App) {
+        app.init_resource::<ChunkRenderSettings>()
+            .add_plugin(MaterialPlugin::<ChunkMaterial>::default())
+            .add_system_set(
+                SystemSet::on_update(AppState::Run)
+                    .with_system(build_mesh.after("light"))
+                    .with_system(update_chunk)
+                    .with_system(build_mesh_done),
+            );
+    }
+}
+
+/// Voxel with the shading of its faces in the order of `RIGHT_HANDED_Y_UP_CONFIG.faces`, so
+/// that faces shaded differently are not merged.
+#[derive(Clone, Copy, Default)]
+struct LitVoxel {
+    voxel: Voxel,
+    faces: [FaceShading; 6],
+}
+
+impl MergeVoxel for LitVoxel {
+    type VoxelValue = (u8, [FaceShading; 6]);
+
+    fn voxel_merge_value(&self) -> Self::VoxelValue {
+        (self.voxel.0, self.faces)
+    }
+}
+
+impl IsOpaque for LitVoxel {
+    fn is_opaque(&self) -> bool {
+        self.voxel.is_opaque()
+    }
+}
+
+impl IsEmpty for LitVoxel {
+    fn is_empty(&self) -> bool {
+        self.voxel.is_empty()
+    }
+}
+
 pub fn update_chunk(
     mut commands: Commands,
     mut meshes: ResMut<Assets<Mesh>>,
     blocks: Res<Blocks>,
     assets: Res<Assets<Block>>,
     asset_server: Res<AssetServer>,
-    mut materials: ResMut<Assets<StandardMaterial>>,
-    query: Query<(Entity, &MeshBuf, &Transform), Changed<MeshBuf>>,
+    mut materials: ResMut<Assets<ChunkMaterial>>,
+    mut block_materials: Local<HashMap<BlockId, Handle<ChunkMaterial>>>,
+    query: Query<(Entity, &MeshBuf, &Transform, Option<&Children>), Changed<MeshBuf>>,
 ) {
-    for (e, mesh_buf, transform) in query.iter() {
+    for (e, mesh_buf, transform, children) in query.iter() {
+        // Meshes of the previous version of the chunk.
+        for child in children.into_iter().flat_map(|children| children.iter()) {
+            commands.entity(*child).despawn_recursive();
+        }
         let data = mesh_buf.clone().data;
         for (block_id, block_meshes) in data.iter() {
             let BlockMesh {
                 positions,
                 tex_coords,
                 normals,
+                colors,
+                ambient_occlusion,
                 indices,
             } = block_meshes.clone();
+            if !block_materials.contains_key(block_id) {
+                // Unknown blocks are not drawn.
+                let block = match blocks.get_block(&assets, block_id) {
+                    Some(block) => block,
+                    None => continue,
+                };
+                let texture = asset_server.get_handle(&*block.texture_name);
+                block_materials.insert(*block_id, materials.add(ChunkMaterial::new(texture)));
+            }
+            let material = block_materials[block_id].clone();
             let mut render_mesh = Mesh::new(PrimitiveTopology::TriangleList);
-            let block = blocks.get_block(&assets, block_id).unwrap();
-            let texture_handle = asset_server.get_handle(&*block.texture_name);
 
             render_mesh.set_attribute(Mesh::ATTRIBUTE_POSITION, positions);
             render_mesh.set_attribute(Mesh::ATTRIBUTE_NORMAL, normals);
             render_mesh.set_attribute(Mesh::ATTRIBUTE_UV_0, tex_coords);
+            render_mesh.set_attribute(Mesh::ATTRIBUTE_COLOR, colors);
+            render_mesh.set_attribute(
+                ChunkMaterial::ATTRIBUTE_AMBIENT_OCCLUSION,
+                ambient_occlusion,
+            );
             render_mesh.set_indices(Some(Indices::U32(indices)));
 
             commands.entity(e).with_children(|builder| {
-                let mut material: StandardMaterial = texture_handle.clone().into();
-                material.reflectance = 0.25 * (*block_id as f32);
-                builder.spawn_bundle(PbrBundle {
+                builder.spawn_bundle(MaterialMeshBundle {
                     mesh: meshes.add(render_mesh),
-                    material: materials.add(material),
+                    material,
                     ..Default::default()
                 });
             });
@@ -57,40 +142,187 @@ pub fn update_chunk(
     }
 }
 
+#[allow(clippy::too_many_arguments)]
 pub fn build_mesh(
     mut commands: Commands,
     pool: Res<ComputeTaskPool>,
-    query: Query<(Entity, &Chunk), Changed<Chunk>>,
+    settings: Res<ChunkRenderSettings>,
+    world: Res<ChunkWorld>,
+    blocks: Res<Blocks>,
+    block_assets: Res<Assets<Block>>,
+    changed: Query<(Entity, &Chunk, &ChunkLight), Or<(Changed<Chunk>, Changed<ChunkLight>)>>,
+    all: Query<(Entity, &Chunk, &ChunkLight)>,
 ) {
-    for (e, chunk) in query.iter() {
+    // Every chunk is meshed again with the new settings.
+    let chunks: Vec<_> = if settings.is_changed() && !settings.is_added() {
+        all.iter().collect()
+    } else {
+        changed.iter().collect()
+    };
+    if chunks.is_empty() {
+        return;
+    }
+    let voxel_blocks = VoxelBlocks::new(&blocks, &block_assets);
+    // Whether the blocks hide the ambient light, by the block part of voxels.
+    let mut occluders = [false; 32];
+    for (block, occluder) in occluders.iter_mut().enumerate() {
+        *occluder = voxel_blocks.is_solid(Voxel::new(block as u8, 0));
+    }
+    let ambient_occlusion = settings.ambient_occlusion;
+
+    for (e, chunk, chunk_light) in chunks {
         let chunk: Chunk = chunk.clone();
+        let extent = *chunk.data.extent();
+        let padded_extent = padded_greedy_quads_chunk_extent(&extent);
+        // The borders are meshed with the voxels and the light of the chunks around, the
+        // missing ones are empty and bright until they are loaded.
+        let bright = VoxelLight {
+            sky: MAX_LIGHT,
+            block: 0,
+        };
+        let mut voxels = Array3x1::fill(padded_extent, Voxel::AIR);
+        let mut light = Array3x1::fill(padded_extent, bright);
+        copy_extent(&extent, &chunk.data, &mut voxels);
+        copy_extent(&extent, chunk_light.data(), &mut light);
+        let around = Extent3i::from_min_and_max(PointN([-1; 3]), PointN([1; 3]));
+        for offset in around
+            .iter_points()
+            .filter(|offset| *offset != PointN([0; 3]))
+        {
+            let pos = chunk.pos + offset;
+            if let Some((_, neighbour, neighbour_light)) =
+                world.world.get(&pos).and_then(|e| all.get(*e).ok())
+            {
+                let border = padded_extent.intersection(&chunk_extent(pos));
+                copy_extent(&border, neighbour.data(), &mut voxels);
+                copy_extent(&border, neighbour_light.data(), &mut light);
+            }
+        }
+
         let task = pool.spawn(async move {
-            let padded_extent = padded_greedy_quads_chunk_extent(chunk.data.extent());
-            let mut data = Array3x1::fill(padded_extent, Voxel::default());
-            copy_extent(&padded_extent, &chunk.data, &mut data);
+            // Flowing liquids are lower than a block, they are not merged into quads.
+            let empty = |voxel: Voxel| voxel.is_empty() || voxel.level() > 0;
+            let occludes = |pos: Point3i| occluders[voxels.get(pos).block() as usize];
+            let mut data = Array3x1::fill(padded_extent, LitVoxel::default());
+            for pos in padded_extent.iter_points() {
+                let voxel = voxels.get(pos);
+                if empty(voxel) {
+                    continue;
+                }
+                let mut faces = [FaceShading::default(); 6];
+                if extent.contains(pos) {
+                    let config_faces = RIGHT_HANDED_Y_UP_CONFIG.faces.iter();
+                    for (shading, face) in faces.iter_mut().zip(config_faces) {
+                        let front = pos + face.signed_normal();
+                        // Hidden faces keep the default to be merged with anything.
+                        if !empty(voxels.get(front)) {
+                            continue;
+                        }
+                        shading.light = light.get(front);
+                        if ambient_occlusion {
+                            shading.occlusion = corner_occlusion(face, front, &occludes);
+                        }
+                    }
+                }
+                *data.get_mut(pos) = LitVoxel { voxel, faces };
+            }
+            let flowing: Vec<_> = extent
+                .iter_points()
+                .filter(|pos| chunk.data.get(*pos).level() > 0)
+                .collect();
 
             let mut greedy_buffer =
                 GreedyQuadsBuffer::new(padded_extent, RIGHT_HANDED_Y_UP_CONFIG.quad_groups());
             greedy_quads(&data, &padded_extent, &mut greedy_buffer);
 
             let mut mesh_buf = MeshBuf::default();
-            for group in greedy_buffer.quad_groups.iter() {
+            for (face, group) in greedy_buffer.quad_groups.iter().enumerate() {
                 for quad in group.quads.iter() {
-                    let mat = &chunk.data.get(quad.minimum);
+                    let lit = data.get(quad.minimum);
                     mesh_buf.add_quad(
                         &group.face,
                         quad,
                         RIGHT_HANDED_Y_UP_CONFIG.u_flip_face,
-                        mat.0 as u32 - 1,
+                        lit.voxel.block() as u32 - 1,
+                        extent.minimum,
+                        lit.faces[face],
                     );
                 }
             }
+            for pos in flowing {
+                let voxel = chunk.data.get(pos);
+                let height = 1.0 - voxel.level() as f32 / (Voxel::MAX_LEVEL + 1) as f32;
+                let neighbour = |offset: [i32; 3]| {
+                    let pos = pos + PointN(offset);
+                    if extent.contains(pos) {
+                        chunk.data.get(pos)
+                    } else {
+                        Voxel::AIR
+                    }
+                };
+                // Sides are hidden by blocks and the full liquids around.
+                let side = |offset| {
+                    let neighbour = neighbour(offset);
+                    neighbour.block() == 0 || neighbour.level() > voxel.level()
+                };
+                let visible = [
+                    side([1, 0, 0]),
+                    side([-1, 0, 0]),
+                    true,
+                    neighbour([0, -1, 0]).block() == 0,
+                    side([0, 0, 1]),
+                    side([0, 0, -1]),
+                ];
+                // The top is inside the voxel, the faces are lit by the brighter side.
+                let mut lights = [VoxelLight::default(); 6];
+                for (light_at, (normal, _)) in lights.iter_mut().zip(BLOCK_FACES.iter()) {
+                    let offset = PointN([normal[0] as i32, normal[1] as i32, normal[2] as i32]);
+                    let (inside, outside) = (light.get(pos), light.get(pos + offset));
+                    *light_at = VoxelLight {
+                        sky: inside.sky.max(outside.sky),
+                        block: inside.block.max(outside.block),
+                    };
+                }
+                let local = pos - extent.minimum;
+                mesh_buf.add_block_faces(
+                    [local.x() as f32, local.y() as f32, local.z() as f32],
+                    height,
+                    visible,
+                    lights,
+                    voxel.block() as u32 - 1,
+                );
+            }
             mesh_buf
         });
         commands.entity(e).insert(task);
     }
 }
 
+/// Neighbours of the voxel in `front` of a face hiding each corner of the face from the
+/// ambient light, in the order of the corners of the quads.
+fn corner_occlusion(
+    face: &OrientedCubeFace,
+    front: Point3i,
+    occludes: &impl Fn(Point3i) -> bool,
+) -> [u8; 4] {
+    let mut occlusion = [0; 4];
+    for (hidden, (u, v)) in occlusion
+        .iter_mut()
+        .zip([(-1, -1), (1, -1), (-1, 1), (1, 1)])
+    {
+        let u = PointN([u; 3]) * face.u;
+        let v = PointN([v; 3]) * face.v;
+        let (side_u, side_v) = (occludes(front + u), occludes(front + v));
+        // Both sides hide the corner whatever is in it.
+        *hidden = if side_u && side_v {
+            3
+        } else {
+            side_u as u8 + side_v as u8 + occludes(front + u + v) as u8
+        };
+    }
+    occlusion
+}
+
 pub fn build_mesh_done(mut commands: Commands, mut query: Query<(Entity, &mut Task<MeshBuf>)>) {
     for (e, mut task) in query.iter_mut() {
         if let Some(mesh_buf) = future::block_on(future::poll_once(&mut *task)) {
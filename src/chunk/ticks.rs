@@ -0,0 +1,422 @@
+use std::cmp::Reverse;
+use std::collections::{BinaryHeap, HashMap, HashSet};
+
+use bevy::core::FixedTimestep;
+use bevy::prelude::*;
+use building_blocks::core::{Point3i, PointN};
+use building_blocks::prelude::{Get, GetMut};
+
+use crate::blocks::{Block, Blocks, VoxelBlocks};
+use crate::chunk::{chunk_extent, voxel_chunk, Chunk, ChunkWorld, Voxel, VoxelChanged};
+
+const NEIGHBOURS: [Point3i; 6] = [
+    PointN([1, 0, 0]),
+    PointN([-1, 0, 0]),
+    PointN([0, 1, 0]),
+    PointN([0, -1, 0]),
+    PointN([0, 0, 1]),
+    PointN([0, 0, -1]),
+];
+
+pub struct TickSettings {
+    /// Block ticks per second, read when `BlockTickPlugin` is added.
+    pub tick_rate: f64,
+    /// Random voxels of every loaded chunk ticked each tick.
+    pub random_ticks_per_chunk: u32,
+    /// Scheduled ticks run per tick at most, the others are late.
+    pub max_scheduled_ticks: usize,
+}
+
+impl Default for TickSettings {
+    fn default() -> Self {
+        TickSettings {
+            tick_rate: 20.0,
+            random_ticks_per_chunk: 3,
+            max_scheduled_ticks: 4096,
+        }
+    }
+}
+
+/// How a block reacts to the world, registered against the name of the block or a property of
+/// it.
+///
+/// Every method is called on the block at `pos` when the tick runs and does nothing by default.
+pub trait BlockBehaviour: Send + Sync + 'static {
+    /// The voxel at `pos` or one of its six neighbours was set.
+    fn neighbour_changed(&self, _world: &mut BlockWorld, _pos: Point3i) {}
+
+    /// A tick scheduled with [`BlockWorld::schedule`] is due.
+    fn scheduled_tick(&self, _world: &mut BlockWorld, _pos: Point3i) {}
+
+    /// The voxel was picked at random among those of its chunk.
+    fn random_tick(&self, _world: &mut BlockWorld, _pos: Point3i) {}
+
+    /// The chunk of the voxel was just generated.
+    fn generated(&self, _world: &mut BlockWorld, _pos: Point3i) {}
+}
+
+#[derive(Default)]
+pub struct BlockBehaviours {
+    by_name: HashMap<String, Vec<Box<dyn BlockBehaviour>>>,
+    /// Behaviours of every block with a property, after the ones registered by name.
+    by_property: Vec<(fn(&Block) -> bool, Box<dyn BlockBehaviour>)>,
+}
+
+impl BlockBehaviours {
+    pub fn add(&mut self, block: &str, behaviour: impl BlockBehaviour) {
+        self.by_name
+            .entry(block.to_owned())
+            .or_default()
+            .push(Box::new(behaviour));
+    }
+
+    pub fn add_for(&mut self, has_property: fn(&Block) -> bool, behaviour: impl BlockBehaviour) {
+        self.by_property.push((has_property, Box::new(behaviour)));
+    }
+
+    /// Behaviours indexed by the block part of voxels.
+    fn by_voxel(&self, blocks: &VoxelBlocks) -> Vec<Vec<&dyn BlockBehaviour>> {
+        (0..32)
+            .map(|block| match blocks.get(Voxel::new(block, 0)) {
+                Some(block) => {
+                    let named = self.by_name.get(&block.name).into_iter().flatten();
+                    let with_property = self
+                        .by_property
+                        .iter()
+                        .filter(|(has_property, _)| has_property(block))
+                        .map(|(_, behaviour)| behaviour);
+                    named
+                        .chain(with_property)
+                        .map(|behaviour| behaviour.as_ref())
+                        .collect()
+                }
+                None => Vec::new(),
+            })
+            .collect()
+    }
+}
+
+pub trait BlockBehaviourAppExt {
+    /// Registers a behaviour for the block called `block`, after the ones already registered.
+    fn add_block_behaviour(&mut self, block: &str, behaviour: impl BlockBehaviour) -> &mut Self;
+
+    /// Registers a behaviour for every block for which `has_property` is true.
+    fn add_block_behaviour_for(
+        &mut self,
+        has_property: fn(&Block) -> bool,
+        behaviour: impl BlockBehaviour,
+    ) -> &mut Self;
+}
+
+impl BlockBehaviourAppExt for App {
+    fn add_block_behaviour(&mut self, block: &str, behaviour: impl BlockBehaviour) -> &mut Self {
+        self.world
+            .get_resource_or_insert_with(BlockBehaviours::default)
+            .add(block, behaviour);
+        self
+    }
+
+    fn add_block_behaviour_for(
+        &mut self,
+        has_property: fn(&Block) -> bool,
+        behaviour: impl BlockBehaviour,
+    ) -> &mut Self {
+        self.world
+            .get_resource_or_insert_with(BlockBehaviours::default)
+            .add_for(has_property, behaviour);
+        self
+    }
+}
+
+#[derive(PartialEq, Eq, PartialOrd, Ord)]
+struct ScheduledTick {
+    due: u64,
+    priority: i32,
+    /// Keeps the ticks with the same due tick and priority in the order they were scheduled.
+    order: u64,
+    pos: [i32; 3],
+}
+
+/// Pending block updates, in world coordinates.
+pub struct BlockTicks {
+    tick: u64,
+    order: u64,
+    scheduled: BinaryHeap<Reverse<ScheduledTick>>,
+    /// Earliest tick scheduled for each position.
+    due: HashMap<Point3i, u64>,
+    notified: Vec<Point3i>,
+    notified_set: HashSet<Point3i>,
+    generated: Vec<Point3i>,
+    random: u64,
+}
+
+impl Default for BlockTicks {
+    fn default() -> Self {
+        BlockTicks {
+            tick: 0,
+            order: 0,
+            scheduled: BinaryHeap::new(),
+            due: HashMap::new(),
+            notified: Vec::new(),
+            notified_set: HashSet::new(),
+            generated: Vec::new(),
+            random: 0x9e37_79b9_7f4a_7c15,
+        }
+    }
+}
+
+impl BlockTicks {
+    /// Number of ticks run so far.
+    pub fn tick(&self) -> u64 {
+        self.tick
+    }
+
+    /// Runs `scheduled_tick` on the block at `pos` in `delay` ticks, at least one.
+    ///
+    /// Ticks due at the same time run by increasing `priority`. Nothing changes if a tick is
+    /// already scheduled for `pos` at that time or before.
+    pub fn schedule(&mut self, pos: Point3i, delay: u64, priority: i32) {
+        let due = self.tick + delay.max(1);
+        if self
+            .due
+            .get(&pos)
+            .map_or(false, |scheduled| *scheduled <= due)
+        {
+            return;
+        }
+        self.due.insert(pos, due);
+        self.order += 1;
+        self.scheduled.push(Reverse(ScheduledTick {
+            due,
+            priority,
+            order: self.order,
+            pos: pos.0,
+        }));
+    }
+
+    /// Runs `neighbour_changed` on `pos` and its neighbours in the next tick.
+    pub fn notify(&mut self, pos: Point3i) {
+        for pos in std::iter::once(pos).chain(NEIGHBOURS.iter().map(|offset| pos + *offset)) {
+            if self.notified_set.insert(pos) {
+                self.notified.push(pos);
+            }
+        }
+    }
+
+    /// Scheduled ticks which did not run yet.
+    pub fn pending(&self) -> usize {
+        self.due.len()
+    }
+
+    fn take_due(&mut self, max: usize) -> Vec<Point3i> {
+        let mut positions = Vec::new();
+        while positions.len() < max {
+            match self.scheduled.peek() {
+                Some(Reverse(scheduled)) if scheduled.due <= self.tick => {}
+                _ => break,
+            }
+            let Reverse(scheduled) = self.scheduled.pop().unwrap();
+            let pos = PointN(scheduled.pos);
+            // Superseded by an earlier tick for the same position.
+            if self.due.get(&pos) == Some(&scheduled.due) {
+                self.due.remove(&pos);
+                positions.push(pos);
+            }
+        }
+        positions
+    }
+
+    /// Random number below `n`.
+    fn random(&mut self, n: u32) -> u32 {
+        self.random ^= self.random << 13;
+        self.random ^= self.random >> 7;
+        self.random ^= self.random << 17;
+        (((self.random >> 32) * n as u64) >> 32) as u32
+    }
+}
+
+/// What the behaviours see of the world while ticking.
+pub struct BlockWorld<'a, 'w, 's> {
+    world: &'a ChunkWorld,
+    chunks: &'a mut Query<'w, 's, &'static mut Chunk>,
+    blocks: &'a VoxelBlocks,
+    ticks: &'a mut BlockTicks,
+    commands: &'a mut Commands<'w, 's>,
+    changes: Vec<VoxelChanged>,
+}
+
+impl<'a, 'w, 's> BlockWorld<'a, 'w, 's> {
+    /// Voxel at `pos`, `None` if its chunk is not loaded.
+    pub fn get(&self, pos: Point3i) -> Option<Voxel> {
+        let chunk = self
+            .chunks
+            .get(*self.world.world.get(&voxel_chunk(pos))?)
+            .ok()?;
+        Some(chunk.data().get(pos))
+    }
+
+    /// Replaces the voxel at `pos`, which notifies it and its neighbours in the next tick.
+    /// Returns whether its chunk is loaded.
+    pub fn set(&mut self, pos: Point3i, voxel: Voxel) -> bool {
+        let chunk = self
+            .world
+            .world
+            .get(&voxel_chunk(pos))
+            .and_then(|e| self.chunks.get_mut(*e).ok());
+        match chunk {
+            Some(mut chunk) => {
+                *chunk.data_mut().get_mut(pos) = voxel;
+                self.changes.push(VoxelChanged { pos, voxel });
+                true
+            }
+            None => false,
+        }
+    }
+
+    pub fn blocks(&self) -> &VoxelBlocks {
+        self.blocks
+    }
+
+    /// Voxel of the block called `name`.
+    pub fn voxel(&self, name: &str) -> Option<Voxel> {
+        self.blocks.find(name)
+    }
+
+    /// See [`BlockTicks::schedule`].
+    pub fn schedule(&mut self, pos: Point3i, delay: u64, priority: i32) {
+        self.ticks.schedule(pos, delay, priority);
+    }
+
+    /// Random number below `n`.
+    pub fn random(&mut self, n: u32) -> u32 {
+        self.ticks.random(n)
+    }
+
+    pub fn commands(&mut self) -> &mut Commands<'w, 's> {
+        self.commands
+    }
+}
+
+/// Runs the block behaviours on a fixed tick, only on the side owning the world.
+pub struct BlockTickPlugin;
+
+impl Plugin for BlockTickPlugin {
+    fn build(&self, app: &mut App) {
+        let tick_rate = app
+            .world
+            .get_resource_or_insert_with(TickSettings::default)
+            .tick_rate;
+        app.init_resource::<BlockTicks>()
+            .init_resource::<BlockBehaviours>()
+            .add_system(collect_changes.label("collect_block_ticks"))
+            .add_system(collect_generated.label("collect_block_ticks"))
+            .add_system_set(
+                SystemSet::new()
+                    .with_run_criteria(FixedTimestep::step(1.0 / tick_rate))
+                    .with_system(run_ticks.after("collect_block_ticks")),
+            );
+    }
+}
+
+fn collect_changes(mut ticks: ResMut<BlockTicks>, mut changes: EventReader<VoxelChanged>) {
+    for change in changes.iter() {
+        ticks.notify(change.pos);
+    }
+}
+
+fn collect_generated(mut ticks: ResMut<BlockTicks>, chunks: Query<&Chunk, Added<Chunk>>) {
+    for chunk in chunks.iter() {
+        ticks.generated.push(chunk.pos());
+    }
+}
+
+#[allow(clippy::too_many_arguments)]
+fn run_ticks<'w, 's>(
+    mut commands: Commands<'w, 's>,
+    mut ticks: ResMut<BlockTicks>,
+    settings: Res<TickSettings>,
+    behaviours: Res<BlockBehaviours>,
+    world: Res<ChunkWorld>,
+    mut chunks: Query<'w, 's, &'static mut Chunk>,
+    blocks: Res<Blocks>,
+    block_assets: Res<Assets<Block>>,
+    mut changes: EventWriter<VoxelChanged>,
+) {
+    let voxel_blocks = VoxelBlocks::new(&blocks, &block_assets);
+    let table = behaviours.by_voxel(&voxel_blocks);
+    let ticks = &mut *ticks;
+
+    let mut notified = std::mem::take(&mut ticks.notified);
+    ticks.notified_set.clear();
+    // Chunks only get into the world a frame after they are spawned.
+    let (generated, waiting): (Vec<_>, Vec<_>) = std::mem::take(&mut ticks.generated)
+        .into_iter()
+        .partition(|pos| world.world.contains_key(pos));
+    ticks.generated = waiting;
+    // Blocks next to a new chunk may have been waiting for it.
+    for chunk_pos in generated.iter() {
+        let padded = chunk_extent(*chunk_pos).padded(1);
+        for offset in NEIGHBOURS.iter() {
+            let layer = padded.intersection(&chunk_extent(*chunk_pos + *offset));
+            notified.extend(layer.iter_points());
+        }
+    }
+    let due = ticks.take_due(settings.max_scheduled_ticks);
+
+    let mut block_world = BlockWorld {
+        world: &world,
+        chunks: &mut chunks,
+        blocks: &voxel_blocks,
+        ticks,
+        commands: &mut commands,
+        changes: Vec::new(),
+    };
+    let behaviours_at = |block_world: &BlockWorld, pos: Point3i| {
+        block_world
+            .get(pos)
+            .map_or(&[][..], |voxel| table[voxel.block() as usize].as_slice())
+    };
+
+    for pos in notified {
+        for behaviour in behaviours_at(&block_world, pos) {
+            behaviour.neighbour_changed(&mut block_world, pos);
+        }
+    }
+    for chunk_pos in generated {
+        for pos in chunk_extent(chunk_pos).iter_points() {
+            for behaviour in behaviours_at(&block_world, pos) {
+                behaviour.generated(&mut block_world, pos);
+            }
+        }
+    }
+    for pos in due {
+        for behaviour in behaviours_at(&block_world, pos) {
+            behaviour.scheduled_tick(&mut block_world, pos);
+        }
+    }
+    for chunk_pos in world.world.keys() {
+        let extent = chunk_extent(*chunk_pos);
+        for _ in 0..settings.random_ticks_per_chunk {
+            let shape = extent.shape;
+            let offset = PointN([
+                block_world.random(shape.x() as u32) as i32,
+                block_world.random(shape.y() as u32) as i32,
+                block_world.random(shape.z() as u32) as i32,
+            ]);
+            let pos = extent.minimum + offset;
+            for behaviour in behaviours_at(&block_world, pos) {
+                behaviour.random_tick(&mut block_world, pos);
+            }
+        }
+    }
+
+    let BlockWorld {
+        changes: block_changes,
+        ticks,
+        ..
+    } = block_world;
+    ticks.tick += 1;
+    for change in block_changes {
+        changes.send(change);
+    }
+}
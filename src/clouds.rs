@@ -0,0 +1,283 @@
+use std::collections::HashMap;
+
+use bevy::pbr::NotShadowCaster;
+use bevy::prelude::*;
+use bevy::render::mesh::Indices;
+use bevy::render::render_resource::PrimitiveTopology;
+use noise::{Fbm, MultiFractal, NoiseFn, Seedable};
+
+use crate::chunk::{Relative, WorldPreset};
+use crate::fog::Fog;
+use crate::AppState;
+
+/// Cells along each side of a cloud tile.
+const TILE_CELLS: i32 = 16;
+/// Added to the world seed so the clouds do not follow the terrain.
+const CLOUD_SEED: u32 = 7919;
+/// Scale of the noise per cell, the larger the smaller the clouds.
+const NOISE_SCALE: f64 = 0.09;
+
+/// Normals and unit corners of the faces of a cloud cell, counter-clockwise seen from outside,
+/// the sides first.
+const CELL_FACES: [([f32; 3], [[f32; 3]; 4]); 6] = [
+    (
+        [1.0, 0.0, 0.0],
+        [
+            [1.0, 0.0, 0.0],
+            [1.0, 1.0, 0.0],
+            [1.0, 1.0, 1.0],
+            [1.0, 0.0, 1.0],
+        ],
+    ),
+    (
+        [-1.0, 0.0, 0.0],
+        [
+            [0.0, 0.0, 0.0],
+            [0.0, 0.0, 1.0],
+            [0.0, 1.0, 1.0],
+            [0.0, 1.0, 0.0],
+        ],
+    ),
+    (
+        [0.0, 0.0, 1.0],
+        [
+            [0.0, 0.0, 1.0],
+            [1.0, 0.0, 1.0],
+            [1.0, 1.0, 1.0],
+            [0.0, 1.0, 1.0],
+        ],
+    ),
+    (
+        [0.0, 0.0, -1.0],
+        [
+            [0.0, 0.0, 0.0],
+            [0.0, 1.0, 0.0],
+            [1.0, 1.0, 0.0],
+            [1.0, 0.0, 0.0],
+        ],
+    ),
+    (
+        [0.0, 1.0, 0.0],
+        [
+            [0.0, 1.0, 0.0],
+            [0.0, 1.0, 1.0],
+            [1.0, 1.0, 1.0],
+            [1.0, 1.0, 0.0],
+        ],
+    ),
+    (
+        [0.0, -1.0, 0.0],
+        [
+            [0.0, 0.0, 0.0],
+            [1.0, 0.0, 0.0],
+            [1.0, 0.0, 1.0],
+            [0.0, 0.0, 1.0],
+        ],
+    ),
+];
+
+/// Cloud layer, the clouds are made again when it changes.
+pub struct CloudSettings {
+    /// Height of the bottom of the clouds.
+    pub altitude: f32,
+    /// Part of the sky the clouds cover, from 0 to 1.
+    pub cover: f32,
+    /// Width of the square cells the clouds are made of, in blocks.
+    pub cell_size: f32,
+    /// Height of the cells, the clouds are flat for zero.
+    pub thickness: f32,
+    /// Blocks per second the clouds drift by, along X and Z.
+    pub wind: Vec2,
+    /// Tiles shown on each side of the one above the camera, fewer when the fog ends closer.
+    pub radius: i32,
+}
+
+impl Default for CloudSettings {
+    fn default() -> Self {
+        CloudSettings {
+            altitude: 140.0,
+            cover: 0.4,
+            cell_size: 8.0,
+            thickness: 4.0,
+            wind: Vec2::new(1.5, 0.5),
+            radius: 2,
+        }
+    }
+}
+
+/// Cloud tiles around the camera, by their position in the drifting layer.
+#[derive(Default)]
+struct CloudTiles {
+    /// How far the layer drifted from the world origin.
+    drift: Vec2,
+    tiles: HashMap<IVec2, Entity>,
+    material: Option<Handle<StandardMaterial>>,
+}
+
+#[derive(Component)]
+struct CloudTile(IVec2);
+
+/// Drifting clouds tiled around the camera, which needs `FogPlugin`.
+pub struct CloudPlugin;
+
+impl Plugin for CloudPlugin {
+    fn build(&self, app: &mut App) {
+        app.init_resource::<CloudSettings>()
+            .init_resource::<CloudTiles>()
+            .add_system_set(
+                SystemSet::on_update(AppState::Run)
+                    .with_system(drift.label("cloud_drift"))
+                    .with_system(update_clouds.after("cloud_drift").after("fog")),
+            );
+    }
+}
+
+fn drift(time: Res<Time>, settings: Res<CloudSettings>, mut clouds: ResMut<CloudTiles>) {
+    clouds.drift += settings.wind * time.delta_seconds();
+}
+
+/// Makes the missing tiles around the camera up to the end of the fog, drops the ones left
+/// behind and moves them all with the drift.
+#[allow(clippy::too_many_arguments)]
+fn update_clouds(
+    mut commands: Commands,
+    settings: Res<CloudSettings>,
+    preset: Option<Res<WorldPreset>>,
+    fog: Res<Fog>,
+    mut clouds: ResMut<CloudTiles>,
+    mut meshes: ResMut<Assets<Mesh>>,
+    mut materials: ResMut<Assets<StandardMaterial>>,
+    viewers: Query<&Transform, (With<Relative>, Without<CloudTile>)>,
+    mut tiles: Query<(&CloudTile, &mut Transform, Option<&mut Visibility>)>,
+) {
+    let camera = match viewers.iter().next() {
+        Some(transform) => transform.translation,
+        None => return,
+    };
+    let preset_changed = preset.as_ref().map_or(false, |preset| preset.is_changed());
+    if settings.is_changed() || preset_changed {
+        for (_, e) in clouds.tiles.drain() {
+            commands.entity(e).despawn();
+        }
+    }
+
+    let tile_size = TILE_CELLS as f32 * settings.cell_size;
+    let drift = clouds.drift;
+    let center = ((Vec2::new(camera.x, camera.z) - drift) / tile_size)
+        .floor()
+        .as_ivec2();
+    // The clouds are not fogged, so no tile starts past the end of the fog.
+    let radius = settings.radius.min((fog.end / tile_size).ceil() as i32);
+    clouds.tiles.retain(|tile, e| {
+        let keep = (*tile - center).abs().max_element() <= radius;
+        if !keep {
+            commands.entity(*e).despawn();
+        }
+        keep
+    });
+
+    let material = clouds
+        .material
+        .get_or_insert_with(|| {
+            materials.add(StandardMaterial {
+                base_color: Color::rgba(1.0, 1.0, 1.0, 0.8),
+                alpha_mode: AlphaMode::Blend,
+                perceptual_roughness: 1.0,
+                ..Default::default()
+            })
+        })
+        .clone();
+    let seed = preset.map_or(WorldPreset::default().seed, |preset| preset.seed) as u32;
+    let mut noise = None;
+    for x in -radius..=radius {
+        for z in -radius..=radius {
+            let tile = center + IVec2::new(x, z);
+            if clouds.tiles.contains_key(&tile) {
+                continue;
+            }
+            let transform = Transform::from_translation(tile_translation(&settings, drift, tile));
+            let noise = noise.get_or_insert_with(|| {
+                Fbm::new()
+                    .set_octaves(4)
+                    .set_seed(seed.wrapping_add(CLOUD_SEED))
+            });
+            let mut entity = match tile_mesh(noise, &settings, tile) {
+                Some(mesh) => commands.spawn_bundle(PbrBundle {
+                    mesh: meshes.add(mesh),
+                    material: material.clone(),
+                    transform,
+                    ..Default::default()
+                }),
+                // Clear sky, kept to know the tile is done.
+                None => commands.spawn_bundle((transform, GlobalTransform::default())),
+            };
+            let e = entity
+                .insert_bundle((CloudTile(tile), NotShadowCaster))
+                .id();
+            clouds.tiles.insert(tile, e);
+        }
+    }
+
+    for (CloudTile(tile), mut transform, visibility) in tiles.iter_mut() {
+        transform.translation = tile_translation(&settings, drift, *tile);
+        // The clouds would show through the fog hiding the sky.
+        if let Some(mut visibility) = visibility {
+            visibility.is_visible = !fog.in_liquid;
+        }
+    }
+}
+
+/// World position of the corner of `tile`.
+fn tile_translation(settings: &CloudSettings, drift: Vec2, tile: IVec2) -> Vec3 {
+    let corner = tile.as_vec2() * TILE_CELLS as f32 * settings.cell_size + drift;
+    Vec3::new(corner.x, settings.altitude, corner.y)
+}
+
+/// Mesh of the cloud cells of `tile` from its corner, `None` for a clear sky.
+fn tile_mesh(noise: &Fbm, settings: &CloudSettings, tile: IVec2) -> Option<Mesh> {
+    // Fbm is mostly between -1 and 1.
+    let threshold = 1.0 - 2.0 * settings.cover.clamp(0.0, 1.0) as f64;
+    let cloudy = |x: i32, z: i32| {
+        let x = (tile.x * TILE_CELLS + x) as f64 * NOISE_SCALE;
+        let z = (tile.y * TILE_CELLS + z) as f64 * NOISE_SCALE;
+        noise.get([x, z]) > threshold
+    };
+    let scale = Vec3::new(settings.cell_size, settings.thickness, settings.cell_size);
+
+    let mut positions = Vec::new();
+    let mut normals = Vec::new();
+    let mut indices = Vec::new();
+    for x in 0..TILE_CELLS {
+        for z in 0..TILE_CELLS {
+            if !cloudy(x, z) {
+                continue;
+            }
+            let cell = Vec3::new(x as f32, 0.0, z as f32);
+            for (normal, corners) in CELL_FACES.iter() {
+                // Sides between cells are hidden, flat clouds have none.
+                let [nx, ny, nz] = *normal;
+                let side = ny == 0.0;
+                if side && (settings.thickness <= 0.0 || cloudy(x + nx as i32, z + nz as i32)) {
+                    continue;
+                }
+                let start = positions.len() as u32;
+                for corner in corners.iter() {
+                    positions.push(((cell + Vec3::from(*corner)) * scale).to_array());
+                    normals.push(*normal);
+                }
+                indices.extend([0, 1, 2, 0, 2, 3].iter().map(|i| start + i));
+            }
+        }
+    }
+    if positions.is_empty() {
+        return None;
+    }
+
+    let mut mesh = Mesh::new(PrimitiveTopology::TriangleList);
+    let tex_coords = vec![[0.0, 0.0]; positions.len()];
+    mesh.set_attribute(Mesh::ATTRIBUTE_POSITION, positions);
+    mesh.set_attribute(Mesh::ATTRIBUTE_NORMAL, normals);
+    mesh.set_attribute(Mesh::ATTRIBUTE_UV_0, tex_coords);
+    mesh.set_indices(Some(Indices::U32(indices)));
+    Some(mesh)
+}
@@ -0,0 +1,128 @@
+use bevy::prelude::*;
+
+use crate::chunk::{ChunkMaterial, Relative, CHUNK_SIZE};
+use crate::player::Player;
+use crate::skysphere::{SkyMaterial, TimeOfDay};
+use crate::AppState;
+
+/// Part of the distance to the edge of the loaded chunks which is clear of fog.
+const FOG_START: f32 = 0.6;
+/// Distance at which the fog in liquids hides everything.
+const LIQUID_FOG_END: f32 = 12.0;
+
+/// Fog fading the chunks into the horizon before the edge of the chunks loaded around the
+/// camera, and hiding everything but what is close when the eyes are in a liquid.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Fog {
+    pub color: Color,
+    /// Distances from the camera the fog starts and hides everything at.
+    pub start: f32,
+    pub end: f32,
+    /// Whether the fog is the one of a liquid, which also hides the sky.
+    pub in_liquid: bool,
+}
+
+impl Default for Fog {
+    fn default() -> Self {
+        Fog {
+            color: Color::NONE,
+            start: f32::MAX,
+            end: f32::MAX,
+            in_liquid: false,
+        }
+    }
+}
+
+impl Fog {
+    /// Whether both fogs look the same, the colour of the sky changes a bit every frame.
+    fn looks_like(&self, other: &Fog) -> bool {
+        let [a, b] = [self.color, other.color].map(|color| color.as_linear_rgba_f32());
+        (self.start, self.end, self.in_liquid) == (other.start, other.end, other.in_liquid)
+            && a.iter().zip(b).all(|(a, b)| (a - b).abs() <= 1.0 / 256.0)
+    }
+}
+
+/// Fog around the camera, which needs `SkyPlugin` and `PlayerPlugin`.
+pub struct FogPlugin;
+
+impl Plugin for FogPlugin {
+    fn build(&self, app: &mut App) {
+        app.init_resource::<Fog>().add_system_set(
+            SystemSet::on_update(AppState::Run)
+                .with_system(update_fog.label("fog").after("time_of_day"))
+                .with_system(apply_fog.after("fog")),
+        );
+    }
+}
+
+fn update_fog(
+    time_of_day: Res<TimeOfDay>,
+    viewers: Query<(&Relative, &Player)>,
+    mut fog: ResMut<Fog>,
+) {
+    let (relative, player) = match viewers.iter().next() {
+        Some(viewer) => viewer,
+        None => return,
+    };
+    let new_fog = match &player.eyes_in {
+        Some(fluid) => {
+            let (r, g, b, _) = fluid.tint;
+            Fog {
+                color: Color::rgb(r, g, b),
+                start: 0.0,
+                end: LIQUID_FOG_END,
+                in_liquid: true,
+            }
+        }
+        None => {
+            // Chunks are loaded at least this far from the camera around it.
+            let end = (relative.0[0].min(relative.0[2]) * CHUNK_SIZE) as f32;
+            Fog {
+                color: time_of_day.horizon_color(),
+                start: end * FOG_START,
+                end,
+                in_liquid: false,
+            }
+        }
+    };
+    if !fog.looks_like(&new_fog) {
+        *fog = new_fog;
+    }
+}
+
+/// Sets the fog of the chunk materials, including the new ones, and hides the sky in liquids.
+fn apply_fog(
+    fog: Res<Fog>,
+    mut chunk_materials: ResMut<Assets<ChunkMaterial>>,
+    mut sky_materials: ResMut<Assets<SkyMaterial>>,
+) {
+    let outdated: Vec<_> = chunk_materials
+        .iter()
+        .filter(|(_, material)| {
+            (material.fog_color, material.fog_start, material.fog_end)
+                != (fog.color, fog.start, fog.end)
+        })
+        .map(|(id, _)| id)
+        .collect();
+    for id in outdated {
+        if let Some(material) = chunk_materials.get_mut(id) {
+            material.fog_color = fog.color;
+            material.fog_start = fog.start;
+            material.fog_end = fog.end;
+        }
+    }
+
+    if fog.is_changed() {
+        let sky_fog = if fog.in_liquid {
+            fog.color
+        } else {
+            Color::NONE
+        };
+        let ids: Vec<_> = sky_materials.iter().map(|(id, _)| id).collect();
+        for id in ids {
+            if let Some(material) = sky_materials.get_mut(id) {
+                material.fog = sky_fog;
+            }
+        }
+    }
+}
@@ -0,0 +1,42 @@
+use bevy::{asset::LoadState, prelude::*};
+
+pub mod blocks;
+pub mod chunk;
+pub mod clouds;
+pub mod fog;
+pub mod generation;
+pub mod net;
+pub mod player;
+pub mod skysphere;
+
+#[derive(Clone, Copy, Debug, Eq, Hash, PartialEq)]
+pub enum AppState {
+    Loading,
+    Run,
+}
+
+/// Assets which have to be loaded before leaving `AppState::Loading`.
+pub struct Loading(pub Vec<HandleUntyped>);
+
+/// Starts in `AppState::Loading` and switches to `AppState::Run` once `Loading` is loaded.
+pub struct LoadingPlugin;
+
+impl Plugin for LoadingPlugin {
+    fn build(&self, app: &mut App) {
+        app.insert_resource(Loading(Vec::new()))
+            .add_state(AppState::Loading)
+            .add_system_set(SystemSet::on_update(AppState::Loading).with_system(wait_loading));
+    }
+}
+
+fn wait_loading(
+    mut state: ResMut<State<AppState>>,
+    loading: Res<Loading>,
+    asset_server: Res<AssetServer>,
+) {
+    if let LoadState::Loaded =
+        asset_server.get_group_load_state(loading.0.iter().map(|handle| handle.id))
+    {
+        state.set(AppState::Run).unwrap(); // TODO!
+    }
+}
@@ -1,60 +1,78 @@
 // mod camera_rotation;
 
 use bevy::diagnostic::LogDiagnosticsPlugin;
-use bevy::{asset::LoadState, prelude::*};
+use bevy::prelude::*;
 use bevy_fly_camera::{FlyCamera, FlyCameraPlugin};
 
-use crate::chunk::{ChunkGeneratorPlugin, Relative};
-use crate::skysphere::SkyPlugin;
-
-mod blocks;
-mod chunk;
-mod generation;
-mod skysphere;
-
-#[derive(Clone, Copy, Debug, Eq, Hash, PartialEq)]
-pub enum AppState {
-    Loading,
-    Run,
-}
-
-struct Loading(Vec<HandleUntyped>);
+use rusted_terra::chunk::{
+    self, BlockTickPlugin, ChunkGeneratorPlugin, ChunkRenderPlugin, FallingBlockPlugin,
+    GrassPlugin, LightPlugin, LiquidPlugin, Relative, WorldPreset,
+};
+use rusted_terra::clouds::CloudPlugin;
+use rusted_terra::fog::FogPlugin;
+use rusted_terra::net::{ClientPlugin, ClientSettings};
+use rusted_terra::player::{Player, PlayerPlugin};
+use rusted_terra::skysphere::SkyPlugin;
+use rusted_terra::{blocks, AppState, LoadingPlugin};
 
 fn main() {
-    App::new()
-        .add_plugins(DefaultPlugins)
-        .insert_resource(State::new(AppState::Loading))
-        .insert_resource(Loading(Vec::new()))
-        .add_state(AppState::Loading)
-        .add_system_set(SystemSet::on_update(AppState::Loading).with_system(wait_loading))
+    // Chunks come from the server when connecting to one, instead of being generated.
+    let mut server = None;
+    let mut preset = WorldPreset::default();
+    let mut args = std::env::args().skip(1);
+    while let Some(arg) = args.next() {
+        match (arg.as_str(), args.next()) {
+            ("--connect", Some(address)) => match address.parse() {
+                Ok(address) => server = Some(address),
+                Err(e) => panic!("Invalid address {}: {}", address, e),
+            },
+            ("--preset", Some(path)) => match WorldPreset::load(&path) {
+                Ok(loaded) => preset = loaded,
+                Err(e) => panic!("Cannot load preset {}: {:?}", path, e),
+            },
+            _ => panic!("Usage: Rusted_Terra [--connect ADDRESS] [--preset PATH]"),
+        }
+    }
+
+    let mut app = App::new();
+    app.add_plugins(DefaultPlugins)
+        .add_plugin(LoadingPlugin)
         .add_system_set(SystemSet::on_update(AppState::Run).with_system(cursor_grab_system))
         .add_system_set(SystemSet::on_enter(AppState::Run).with_system(setup))
         .add_plugin(chunk::ChunkPlugin)
+        .add_plugin(LightPlugin)
+        .add_plugin(ChunkRenderPlugin)
         .add_plugin(FlyCameraPlugin)
+        .add_plugin(PlayerPlugin)
         .add_plugin(blocks::BlockPlugin)
+        .add_plugin(FallingBlockPlugin)
         .add_plugin(LogDiagnosticsPlugin::default())
         .add_plugin(SkyPlugin)
-        .add_plugin(ChunkGeneratorPlugin)
-        .run();
-}
-
-fn wait_loading(
-    mut state: ResMut<State<AppState>>,
-    loading: Res<Loading>,
-    asset_server: Res<AssetServer>,
-) {
-    if let LoadState::Loaded =
-        asset_server.get_group_load_state(loading.0.iter().map(|handle| handle.id))
-    {
-        state.set(AppState::Run).unwrap(); // TODO!
-    }
+        .add_plugin(FogPlugin)
+        .add_plugin(CloudPlugin);
+    match server {
+        Some(server) => app
+            .insert_resource(ClientSettings {
+                server,
+                ..Default::default()
+            })
+            .add_plugin(ClientPlugin),
+        None => app
+            .insert_resource(preset)
+            .add_plugin(ChunkGeneratorPlugin)
+            .add_plugin(BlockTickPlugin)
+            .add_plugin(LiquidPlugin)
+            .add_plugin(GrassPlugin),
+    };
+    app.run();
 }
 
 fn setup(mut commands: Commands) {
     commands
         .spawn_bundle(PerspectiveCameraBundle::new_3d())
         .insert(FlyCamera::default())
-        .insert(crate::chunk::Relative([2; 3]));
+        .insert(Player::default())
+        .insert(Relative([2; 3]));
 }
 
 fn cursor_grab_system(
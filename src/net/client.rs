@@ -0,0 +1,293 @@
+use std::net::{Ipv4Addr, SocketAddr};
+
+use bevy::core::FixedTimestep;
+use bevy::prelude::*;
+use building_blocks::core::PointN;
+use building_blocks::prelude::GetMut;
+
+use super::players::{interpolate_players, receive_players, PlayersReceived};
+use super::protocol::{
+    ClientMessage, Connection, ServerMessage, DEFAULT_PORT, PROTOCOL_VERSION, TICK_RATE,
+};
+use super::{BlockEditRequest, RemotePlayer};
+use crate::chunk::{
+    chunk_extent, voxel_chunk, Chunk, ChunkLight, ChunkWorld, FallingBlock, Relative,
+    RemoteFallingBlock, Voxel, VoxelChanged, WorldPreset, CHUNK_SIZE,
+};
+use crate::AppState;
+
+pub struct ClientSettings {
+    pub server: SocketAddr,
+    /// Distance in chunks the server is asked to stream around the viewer.
+    pub view_distance: u8,
+}
+
+impl Default for ClientSettings {
+    fn default() -> Self {
+        ClientSettings {
+            server: (Ipv4Addr::LOCALHOST, DEFAULT_PORT).into(),
+            view_distance: 2,
+        }
+    }
+}
+
+/// Connection to the server, removed once it is lost.
+pub struct Client {
+    connection: Connection,
+    /// View distance granted by the server, `None` until welcomed.
+    view_distance: Option<u8>,
+    /// Id the other players see us as, `None` until welcomed.
+    player_id: Option<u32>,
+}
+
+impl Client {
+    pub fn view_distance(&self) -> Option<u8> {
+        self.view_distance
+    }
+
+    pub fn player_id(&self) -> Option<u32> {
+        self.player_id
+    }
+}
+
+/// Receives the chunks around the viewer from a server instead of generating them.
+pub struct ClientPlugin;
+
+impl Plugin for ClientPlugin {
+    fn build(&self, app: &mut App) {
+        app.init_resource::<ClientSettings>()
+            .add_event::<BlockEditRequest>()
+            .add_event::<PlayersReceived>()
+            .add_startup_system(connect)
+            .add_system_set(
+                SystemSet::on_update(AppState::Run)
+                    .label("network")
+                    .with_system(receive.label("receive"))
+                    .with_system(receive_players.label("players").after("receive"))
+                    .with_system(interpolate_players.after("players"))
+                    .with_system(send_view_distance.label("send").after("receive"))
+                    .with_system(send_edits.label("send").after("receive"))
+                    .with_system(flush.after("send")),
+            )
+            .add_system_set(
+                SystemSet::new()
+                    .with_run_criteria(FixedTimestep::step(1.0 / TICK_RATE))
+                    .with_system(send_movement.label("send").after("receive")),
+            );
+    }
+}
+
+fn connect(mut commands: Commands, settings: Res<ClientSettings>) {
+    match Connection::connect(settings.server) {
+        Ok(mut connection) => {
+            info!("Connected to {}", settings.server);
+            connection.send(&ClientMessage::Hello {
+                version: PROTOCOL_VERSION,
+                view_distance: settings.view_distance,
+            });
+            commands.insert_resource(Client {
+                connection,
+                view_distance: None,
+                player_id: None,
+            });
+        }
+        Err(e) => error!("Cannot connect to {} {:?}", settings.server, e),
+    }
+}
+
+fn receive(
+    mut commands: Commands,
+    client: Option<ResMut<Client>>,
+    mut world: ResMut<ChunkWorld>,
+    mut chunks: Query<&mut Chunk>,
+    falling: Query<(Entity, &FallingBlock, &Transform)>,
+    mut players: EventWriter<PlayersReceived>,
+    mut changes: EventWriter<VoxelChanged>,
+) {
+    let mut client = match client {
+        Some(client) => client,
+        None => return,
+    };
+    // Falling blocks spawned by this batch, which the query does not see yet.
+    let mut new_falling = Vec::new();
+    loop {
+        let message = match client.connection.receive::<ServerMessage>() {
+            Ok(Some(message)) => message,
+            Ok(None) => break,
+            Err(e) => {
+                error!("Disconnected from the server {:?}", e);
+                commands.remove_resource::<Client>();
+                break;
+            }
+        };
+        match message {
+            ServerMessage::Welcome {
+                view_distance,
+                player_id,
+                seed,
+            } => {
+                info!(
+                    "Joined as player {} with a view distance of {}",
+                    player_id, view_distance
+                );
+                client.view_distance = Some(view_distance);
+                client.player_id = Some(player_id);
+                commands.insert_resource(WorldPreset {
+                    seed,
+                    ..Default::default()
+                });
+            }
+            ServerMessage::Rejected(reason) => {
+                error!("Rejected by the server: {}", reason);
+                commands.remove_resource::<Client>();
+                break;
+            }
+            ServerMessage::Chunk { pos, voxels } => {
+                let pos = PointN(pos);
+                let data = match voxels.decompress(chunk_extent(pos)) {
+                    Ok(data) => data,
+                    Err(e) => {
+                        error!("Chunk {:?} cannot be read {:?}", pos, e);
+                        continue;
+                    }
+                };
+                let chunk = Chunk::new(pos, data);
+                match world.world.get(&pos).copied() {
+                    Some(e) => match chunks.get_mut(e) {
+                        // Lit again from scratch.
+                        Ok(mut old) => {
+                            *old = chunk;
+                            commands.entity(e).remove::<ChunkLight>();
+                        }
+                        Err(_) => {
+                            commands.entity(e).insert(chunk);
+                        }
+                    },
+                    None => {
+                        let transform = Transform::from_translation(Vec3::from([
+                            (pos.x() * CHUNK_SIZE) as f32,
+                            (pos.y() * CHUNK_SIZE) as f32,
+                            (pos.z() * CHUNK_SIZE) as f32,
+                        ]));
+                        let e = commands.spawn().insert(chunk).insert(transform).id();
+                        world.world.insert(pos, e);
+                    }
+                }
+            }
+            ServerMessage::UnloadChunk(pos) => {
+                if let Some(e) = world.world.remove(&PointN(pos)) {
+                    commands.entity(e).despawn_recursive();
+                }
+                // They would wait for the chunk forever.
+                despawn_falling(
+                    &mut commands,
+                    &falling,
+                    &mut new_falling,
+                    |translation, _| {
+                        voxel_chunk(PointN(translation.floor().as_ivec3().to_array()))
+                            == PointN(pos)
+                    },
+                );
+            }
+            ServerMessage::BlockChanged { pos, voxel } => {
+                let pos = PointN(pos);
+                let chunk = world
+                    .world
+                    .get(&voxel_chunk(pos))
+                    .and_then(|e| chunks.get_mut(*e).ok());
+                if let Some(mut chunk) = chunk {
+                    *chunk.data_mut().get_mut(pos) = Voxel(voxel);
+                    changes.send(VoxelChanged {
+                        pos,
+                        voxel: Voxel(voxel),
+                    });
+                }
+                // The server placed a falling block where it landed.
+                despawn_falling(
+                    &mut commands,
+                    &falling,
+                    &mut new_falling,
+                    |translation, block| {
+                        block.0 == voxel
+                            && translation.x as i32 == pos.x()
+                            && translation.z as i32 == pos.z()
+                            && pos.y() as f32 <= translation.y
+                    },
+                );
+            }
+            ServerMessage::BlockFell { pos, voxel } => {
+                let pos = PointN(pos);
+                if world.world.contains_key(&voxel_chunk(pos)) {
+                    let e = FallingBlock::spawn(&mut commands, pos, Voxel(voxel));
+                    commands.entity(e).insert(RemoteFallingBlock);
+                    let translation = Vec3::new(pos.x() as f32, pos.y() as f32, pos.z() as f32);
+                    new_falling.push((e, translation, Voxel(voxel)));
+                }
+            }
+            ServerMessage::Players(states) => players.send(PlayersReceived(states)),
+        }
+    }
+}
+
+/// Despawns the falling blocks for which `gone` is true, given their translation and voxel.
+fn despawn_falling(
+    commands: &mut Commands,
+    falling: &Query<(Entity, &FallingBlock, &Transform)>,
+    new_falling: &mut Vec<(Entity, Vec3, Voxel)>,
+    gone: impl Fn(Vec3, Voxel) -> bool,
+) {
+    let spawned = falling
+        .iter()
+        .map(|(e, block, transform)| (e, transform.translation, block.voxel));
+    for (e, translation, voxel) in spawned.chain(new_falling.iter().copied()) {
+        if gone(translation, voxel) {
+            commands.entity(e).despawn_recursive();
+        }
+    }
+    new_falling.retain(|(_, translation, voxel)| !gone(*translation, *voxel));
+}
+
+/// Sends where the viewer is and where it looks, at the tick rate of the server.
+fn send_movement(
+    client: Option<ResMut<Client>>,
+    viewers: Query<&Transform, (With<Relative>, Without<RemotePlayer>)>,
+) {
+    if let (Some(mut client), Some(transform)) = (client, viewers.iter().next()) {
+        client.connection.send(&ClientMessage::Move {
+            translation: transform.translation.to_array(),
+            rotation: transform.rotation.to_array(),
+        });
+    }
+}
+
+/// Asks the server for another view distance when the settings change.
+fn send_view_distance(client: Option<ResMut<Client>>, settings: Res<ClientSettings>) {
+    if let Some(mut client) = client {
+        // The first view distance is part of `Hello`.
+        if settings.is_changed() && !settings.is_added() {
+            client
+                .connection
+                .send(&ClientMessage::ViewDistance(settings.view_distance));
+        }
+    }
+}
+
+fn send_edits(client: Option<ResMut<Client>>, mut edits: EventReader<BlockEditRequest>) {
+    if let Some(mut client) = client {
+        for edit in edits.iter() {
+            client.connection.send(&ClientMessage::EditBlock {
+                pos: edit.pos.0,
+                voxel: edit.voxel.0,
+            });
+        }
+    }
+}
+
+fn flush(mut commands: Commands, client: Option<ResMut<Client>>) {
+    if let Some(mut client) = client {
+        if let Err(e) = client.connection.flush() {
+            error!("Disconnected from the server {:?}", e);
+            commands.remove_resource::<Client>();
+        }
+    }
+}
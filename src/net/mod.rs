@@ -0,0 +1,22 @@
+use building_blocks::core::Point3i;
+
+use crate::chunk::Voxel;
+
+pub use client::{Client, ClientPlugin, ClientSettings};
+pub use players::{PlayersReceived, RemotePlayer};
+pub use protocol::{
+    ClientMessage, CompressedVoxels, Connection, PlayerState, ServerMessage, DEFAULT_PORT,
+    PROTOCOL_VERSION, TICK_RATE,
+};
+pub use server::{RemoteClient, Server, ServerPlugin, ServerSettings};
+
+mod client;
+mod players;
+mod protocol;
+mod server;
+
+/// Asks to replace the voxel at `pos`, sent to the server by clients and applied by the server.
+pub struct BlockEditRequest {
+    pub pos: Point3i,
+    pub voxel: Voxel,
+}
@@ -0,0 +1,175 @@
+use std::collections::{HashMap, HashSet, VecDeque};
+
+use bevy::prelude::*;
+
+use super::protocol::{PlayerState, TICK_RATE};
+
+/// Remote players are shown this far in the past, so that there usually is a snapshot on
+/// both sides of the shown time.
+const INTERPOLATION_DELAY: f64 = 2.0 / TICK_RATE;
+/// How long a player keeps moving along its last velocity when snapshots are late.
+const MAX_EXTRAPOLATION: f64 = 0.25;
+/// Snapshots older than this behind the shown time are dropped.
+const HISTORY: f64 = 1.0;
+
+/// Players sent by the server in one `ServerMessage::Players`.
+pub struct PlayersReceived(pub Vec<PlayerState>);
+
+struct Snapshot {
+    /// Local time the snapshot was received at.
+    time: f64,
+    translation: Vec3,
+    rotation: Quat,
+}
+
+/// Another player connected to the same server, its `Transform` is interpolated between the
+/// received states.
+#[derive(Component)]
+pub struct RemotePlayer {
+    id: u32,
+    snapshots: VecDeque<Snapshot>,
+}
+
+impl RemotePlayer {
+    fn new(id: u32) -> Self {
+        RemotePlayer {
+            id,
+            snapshots: VecDeque::new(),
+        }
+    }
+
+    pub fn id(&self) -> u32 {
+        self.id
+    }
+
+    fn push(&mut self, time: f64, state: &PlayerState) {
+        self.snapshots.push_back(Snapshot {
+            time,
+            translation: Vec3::from(state.translation),
+            rotation: Quat::from_array(state.rotation).normalize(),
+        });
+    }
+
+    /// Drops the snapshots which are not needed to sample `time` or later.
+    fn forget_before(&mut self, time: f64) {
+        while self.snapshots.len() > 2 && self.snapshots[1].time < time - HISTORY {
+            self.snapshots.pop_front();
+        }
+    }
+
+    /// Translation and rotation at `time`, extrapolated for a while past the last snapshot.
+    fn sample(&self, time: f64) -> Option<(Vec3, Quat)> {
+        let last = self.snapshots.back()?;
+        if time >= last.time {
+            let previous = match self.snapshots.len() {
+                1 => return Some((last.translation, last.rotation)),
+                len => &self.snapshots[len - 2],
+            };
+            let span = (last.time - previous.time).max(f64::EPSILON);
+            let velocity = (last.translation - previous.translation) / span as f32;
+            let ahead = (time - last.time).min(MAX_EXTRAPOLATION);
+            return Some((last.translation + velocity * ahead as f32, last.rotation));
+        }
+
+        let next = self
+            .snapshots
+            .iter()
+            .position(|snapshot| snapshot.time > time)
+            .unwrap();
+        if next == 0 {
+            let first = &self.snapshots[0];
+            return Some((first.translation, first.rotation));
+        }
+        let (a, b) = (&self.snapshots[next - 1], &self.snapshots[next]);
+        let t = ((time - a.time) / (b.time - a.time)) as f32;
+        Some((
+            a.translation.lerp(b.translation, t),
+            a.rotation.slerp(b.rotation, t),
+        ))
+    }
+}
+
+/// Spawns the players which joined, despawns the ones which left and records the states of
+/// the others.
+pub(super) fn receive_players(
+    mut commands: Commands,
+    time: Res<Time>,
+    mut received: EventReader<PlayersReceived>,
+    mut players: Query<(Entity, &mut RemotePlayer)>,
+    meshes: Option<ResMut<Assets<Mesh>>>,
+    materials: Option<ResMut<Assets<StandardMaterial>>>,
+    mut avatar: Local<Option<(Handle<Mesh>, Handle<StandardMaterial>)>>,
+) {
+    let now = time.seconds_since_startup();
+    let mut existing: HashMap<_, _> = players
+        .iter_mut()
+        .map(|(e, player)| (player.id, (e, player)))
+        .collect();
+    let mut joined: HashMap<u32, RemotePlayer> = HashMap::new();
+    for PlayersReceived(states) in received.iter() {
+        let ids: HashSet<_> = states.iter().map(|state| state.id).collect();
+        existing.retain(|id, (e, _)| {
+            if !ids.contains(id) {
+                commands.entity(*e).despawn_recursive();
+            }
+            ids.contains(id)
+        });
+        joined.retain(|id, _| ids.contains(id));
+        for state in states {
+            match existing.get_mut(&state.id) {
+                Some((_, player)) => player.push(now, state),
+                None => joined
+                    .entry(state.id)
+                    .or_insert_with(|| RemotePlayer::new(state.id))
+                    .push(now, state),
+            }
+        }
+    }
+
+    // Avatars are only shown when rendering, headless clients just track the players.
+    if let (Some(mut meshes), Some(mut materials)) = (meshes, materials) {
+        if avatar.is_none() {
+            *avatar = Some((
+                meshes.add(Mesh::from(shape::Cube { size: 0.6 })),
+                materials.add(Color::rgb(0.8, 0.3, 0.2).into()),
+            ));
+        }
+    }
+    for (_, player) in joined {
+        let transform = player
+            .sample(now)
+            .map(|(translation, rotation)| {
+                Transform::from_translation(translation).with_rotation(rotation)
+            })
+            .unwrap_or_default();
+        let mut entity = commands.spawn();
+        entity
+            .insert(player)
+            .insert(transform)
+            .insert(GlobalTransform::default());
+        if let Some((mesh, material)) = avatar.as_ref() {
+            entity.with_children(|builder| {
+                builder.spawn_bundle(PbrBundle {
+                    mesh: mesh.clone(),
+                    material: material.clone(),
+                    ..Default::default()
+                });
+            });
+        }
+    }
+}
+
+/// Moves the remote players to where they were `INTERPOLATION_DELAY` ago.
+pub(super) fn interpolate_players(
+    time: Res<Time>,
+    mut players: Query<(&mut RemotePlayer, &mut Transform)>,
+) {
+    let shown = time.seconds_since_startup() - INTERPOLATION_DELAY;
+    for (mut player, mut transform) in players.iter_mut() {
+        player.forget_before(shown);
+        if let Some((translation, rotation)) = player.sample(shown) {
+            transform.translation = translation;
+            transform.rotation = rotation;
+        }
+    }
+}
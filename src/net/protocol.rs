@@ -0,0 +1,211 @@
+use std::io::{ErrorKind, Read, Write};
+use std::net::{SocketAddr, TcpStream};
+use std::time::Duration;
+
+use anyhow::bail;
+use building_blocks::core::Extent3i;
+use building_blocks::prelude::{Array3x1, Get, GetMut};
+use serde::{de::DeserializeOwned, Deserialize, Serialize};
+
+use crate::chunk::{Seed, Voxel};
+
+/// Bumped on every incompatible change of the messages below.
+pub const PROTOCOL_VERSION: u32 = 4;
+
+pub const DEFAULT_PORT: u16 = 25580;
+
+/// Players are replicated this many times per second.
+pub const TICK_RATE: f64 = 20.0;
+
+/// Longer frames are treated as a broken connection.
+const MAX_FRAME_LEN: usize = 1 << 20;
+
+/// `Hello` has to stay the first variant with the same fields, so that clients of any
+/// version can be told their version is not supported.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub enum ClientMessage {
+    Hello {
+        version: u32,
+        view_distance: u8,
+    },
+    Move {
+        translation: [f32; 3],
+        rotation: [f32; 4],
+    },
+    ViewDistance(u8),
+    EditBlock {
+        pos: [i32; 3],
+        voxel: u8,
+    },
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub enum ServerMessage {
+    Welcome {
+        view_distance: u8,
+        player_id: u32,
+        /// Seed of the world, for what the client makes itself such as the clouds.
+        seed: Seed,
+    },
+    Rejected(String),
+    Chunk {
+        pos: [i32; 3],
+        voxels: CompressedVoxels,
+    },
+    UnloadChunk([i32; 3]),
+    BlockChanged {
+        pos: [i32; 3],
+        voxel: u8,
+    },
+    /// The block at `pos` started falling, it is emptied by a `BlockChanged` and filled again
+    /// where it lands.
+    BlockFell {
+        pos: [i32; 3],
+        voxel: u8,
+    },
+    /// Every other player, the ones missing have left.
+    Players(Vec<PlayerState>),
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct PlayerState {
+    pub id: u32,
+    pub translation: [f32; 3],
+    pub rotation: [f32; 4],
+}
+
+/// Run-length encoded voxels, in the order of `Extent3i::iter_points`.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct CompressedVoxels(Vec<(u16, u8)>);
+
+impl CompressedVoxels {
+    pub fn compress(data: &Array3x1<Voxel>) -> Self {
+        let mut runs: Vec<(u16, u8)> = Vec::new();
+        for pos in data.extent().iter_points() {
+            let voxel = data.get(pos).0;
+            match runs.last_mut() {
+                Some((len, last)) if *last == voxel && *len < u16::MAX => *len += 1,
+                _ => runs.push((1, voxel)),
+            }
+        }
+        CompressedVoxels(runs)
+    }
+
+    pub fn decompress(&self, extent: Extent3i) -> anyhow::Result<Array3x1<Voxel>> {
+        let len: usize = self.0.iter().map(|(len, _)| *len as usize).sum();
+        if len != extent.num_points() as usize {
+            bail!(
+                "Chunk has {} voxels instead of {}",
+                len,
+                extent.num_points()
+            );
+        }
+        let mut data = Array3x1::fill(extent, Voxel::default());
+        let mut voxels = self
+            .0
+            .iter()
+            .flat_map(|(len, voxel)| std::iter::repeat(Voxel(*voxel)).take(*len as usize));
+        for pos in extent.iter_points() {
+            *data.get_mut(pos) = voxels.next().unwrap();
+        }
+        Ok(data)
+    }
+}
+
+/// Length prefixed messages over a non-blocking TCP stream.
+pub struct Connection {
+    stream: TcpStream,
+    incoming: Vec<u8>,
+    outgoing: Vec<u8>,
+    /// The peer closed the connection, the messages it sent before can still be received.
+    closed: bool,
+}
+
+impl Connection {
+    pub fn new(stream: TcpStream) -> anyhow::Result<Self> {
+        stream.set_nonblocking(true)?;
+        stream.set_nodelay(true)?;
+        Ok(Connection {
+            stream,
+            incoming: Vec::new(),
+            outgoing: Vec::new(),
+            closed: false,
+        })
+    }
+
+    pub fn connect(address: SocketAddr) -> anyhow::Result<Self> {
+        Connection::new(TcpStream::connect_timeout(
+            &address,
+            Duration::from_secs(5),
+        )?)
+    }
+
+    pub fn peer_addr(&self) -> Option<SocketAddr> {
+        self.stream.peer_addr().ok()
+    }
+
+    /// Queues `message`, it is written by `flush`.
+    pub fn send<M: Serialize>(&mut self, message: &M) {
+        let bytes = bincode::serialize(message).expect("Messages can always be serialized");
+        self.outgoing
+            .extend_from_slice(&(bytes.len() as u32).to_le_bytes());
+        self.outgoing.extend_from_slice(&bytes);
+    }
+
+    /// Writes as much of the queued messages as the socket accepts.
+    pub fn flush(&mut self) -> anyhow::Result<()> {
+        while !self.outgoing.is_empty() {
+            match self.stream.write(&self.outgoing) {
+                Ok(0) => bail!("Connection closed"),
+                Ok(written) => {
+                    self.outgoing.drain(..written);
+                }
+                Err(e) if e.kind() == ErrorKind::WouldBlock => break,
+                Err(e) if e.kind() == ErrorKind::Interrupted => {}
+                Err(e) => return Err(e.into()),
+            }
+        }
+        Ok(())
+    }
+
+    /// The next complete message, `None` until one has fully arrived.
+    pub fn receive<M: DeserializeOwned>(&mut self) -> anyhow::Result<Option<M>> {
+        let mut buffer = [0; 16 * 1024];
+        while !self.closed {
+            match self.stream.read(&mut buffer) {
+                Ok(0) => self.closed = true,
+                Ok(read) => self.incoming.extend_from_slice(&buffer[..read]),
+                Err(e) if e.kind() == ErrorKind::WouldBlock => break,
+                Err(e) if e.kind() == ErrorKind::Interrupted => {}
+                Err(e) => return Err(e.into()),
+            }
+        }
+
+        match self.next_frame()? {
+            Some(frame) => {
+                let message = bincode::deserialize(&self.incoming[4..4 + frame])?;
+                self.incoming.drain(..4 + frame);
+                Ok(Some(message))
+            }
+            None if self.closed => bail!("Connection closed"),
+            None => Ok(None),
+        }
+    }
+
+    /// Length of the first frame if it has fully arrived.
+    fn next_frame(&self) -> anyhow::Result<Option<usize>> {
+        if self.incoming.len() < 4 {
+            return Ok(None);
+        }
+        let mut len = [0; 4];
+        len.copy_from_slice(&self.incoming[..4]);
+        let len = u32::from_le_bytes(len) as usize;
+        if len > MAX_FRAME_LEN {
+            bail!("Message of {} bytes is too long", len);
+        }
+        if self.incoming.len() < 4 + len {
+            return Ok(None);
+        }
+        Ok(Some(len))
+    }
+}
@@ -0,0 +1,381 @@
+use std::collections::HashSet;
+use std::io::ErrorKind;
+use std::net::{Ipv4Addr, SocketAddr, TcpListener};
+
+use bevy::core::FixedTimestep;
+use bevy::prelude::*;
+use building_blocks::core::{Extent3i, Point3i, PointN};
+use building_blocks::prelude::GetMut;
+
+use super::protocol::{
+    ClientMessage, CompressedVoxels, Connection, PlayerState, ServerMessage, DEFAULT_PORT,
+    PROTOCOL_VERSION, TICK_RATE,
+};
+use super::BlockEditRequest;
+use crate::blocks::{Block, Blocks, VoxelBlocks};
+use crate::chunk::{
+    voxel_chunk, Chunk, ChunkLocation, ChunkWorld, FallingBlock, Relative, Voxel, VoxelChanged,
+    WorldPreset,
+};
+use crate::AppState;
+
+pub struct ServerSettings {
+    pub address: SocketAddr,
+    /// Clients asking for a larger view distance get this one.
+    pub max_view_distance: u8,
+    /// Maximum number of chunks sent to a client per update.
+    pub chunks_per_update: usize,
+}
+
+impl Default for ServerSettings {
+    fn default() -> Self {
+        ServerSettings {
+            address: (Ipv4Addr::UNSPECIFIED, DEFAULT_PORT).into(),
+            max_view_distance: 4,
+            chunks_per_update: 16,
+        }
+    }
+}
+
+pub struct Server {
+    listener: TcpListener,
+}
+
+impl Server {
+    pub fn local_addr(&self) -> SocketAddr {
+        self.listener.local_addr().unwrap()
+    }
+}
+
+/// A connected client, which views the world from its `Transform` once welcomed.
+#[derive(Component)]
+pub struct RemoteClient {
+    connection: Connection,
+    player_id: u32,
+    welcomed: bool,
+    position: Vec3,
+    rotation: Quat,
+    /// Chunks the client has.
+    sent: HashSet<Point3i>,
+}
+
+impl RemoteClient {
+    pub fn player_id(&self) -> u32 {
+        self.player_id
+    }
+
+    fn send(&mut self, message: &ServerMessage) {
+        self.connection.send(message);
+    }
+}
+
+/// Owns the `ChunkWorld` and streams it to clients connecting over TCP.
+pub struct ServerPlugin;
+
+impl Plugin for ServerPlugin {
+    fn build(&self, app: &mut App) {
+        app.init_resource::<ServerSettings>()
+            .add_event::<BlockEditRequest>()
+            .add_startup_system(bind)
+            .add_system_set(
+                SystemSet::on_update(AppState::Run)
+                    .label("network")
+                    .with_system(accept.label("accept"))
+                    .with_system(receive.label("receive").after("accept"))
+                    .with_system(follow_clients.after("receive"))
+                    .with_system(apply_edits.label("edits").after("receive"))
+                    .with_system(send_changes.label("changes").after("edits"))
+                    .with_system(send_falling_blocks.label("changes").after("edits"))
+                    .with_system(stream_chunks.label("stream").after("changes"))
+                    .with_system(flush.after("stream")),
+            )
+            .add_system_set(
+                SystemSet::new()
+                    .with_run_criteria(FixedTimestep::step(1.0 / TICK_RATE))
+                    .with_system(replicate_players.after("receive").before("stream")),
+            );
+    }
+}
+
+fn bind(mut commands: Commands, settings: Res<ServerSettings>) {
+    match TcpListener::bind(settings.address) {
+        Ok(listener) => {
+            listener.set_nonblocking(true).unwrap();
+            info!("Listening on {}", listener.local_addr().unwrap());
+            commands.insert_resource(Server { listener });
+        }
+        Err(e) => panic!("Cannot listen on {}: {}", settings.address, e),
+    }
+}
+
+fn accept(mut commands: Commands, server: Res<Server>, mut next_player_id: Local<u32>) {
+    loop {
+        match server.listener.accept() {
+            Ok((stream, address)) => match Connection::new(stream) {
+                Ok(connection) => {
+                    info!("Client {} connected", address);
+                    commands.spawn().insert(RemoteClient {
+                        connection,
+                        player_id: *next_player_id,
+                        welcomed: false,
+                        position: Vec3::ZERO,
+                        rotation: Quat::IDENTITY,
+                        sent: HashSet::new(),
+                    });
+                    *next_player_id += 1;
+                }
+                Err(e) => warn!("Client {} cannot be set up {:?}", address, e),
+            },
+            Err(e) if e.kind() == ErrorKind::WouldBlock => break,
+            Err(e) => {
+                warn!("Client cannot be accepted {:?}", e);
+                break;
+            }
+        }
+    }
+}
+
+fn disconnect(commands: &mut Commands, e: Entity, client: &RemoteClient, reason: &str) {
+    match client.connection.peer_addr() {
+        Some(address) => info!("Client {} disconnected: {}", address, reason),
+        None => info!("Client disconnected: {}", reason),
+    }
+    commands.entity(e).despawn();
+}
+
+fn receive(
+    mut commands: Commands,
+    settings: Res<ServerSettings>,
+    preset: Res<WorldPreset>,
+    mut clients: Query<(Entity, &mut RemoteClient)>,
+    mut edits: EventWriter<BlockEditRequest>,
+) {
+    for (e, mut client) in clients.iter_mut() {
+        loop {
+            let message = match client.connection.receive::<ClientMessage>() {
+                Ok(Some(message)) => message,
+                Ok(None) => break,
+                Err(error) => {
+                    disconnect(&mut commands, e, &client, &error.to_string());
+                    break;
+                }
+            };
+            match message {
+                ClientMessage::Hello {
+                    version,
+                    view_distance,
+                } => {
+                    if version != PROTOCOL_VERSION {
+                        let reason = format!(
+                            "Protocol version {} is not supported, the server uses {}",
+                            version, PROTOCOL_VERSION
+                        );
+                        client.send(&ServerMessage::Rejected(reason.clone()));
+                        let _ = client.connection.flush();
+                        disconnect(&mut commands, e, &client, &reason);
+                        break;
+                    }
+                    let view_distance = view_distance.min(settings.max_view_distance);
+                    client.welcomed = true;
+                    let player_id = client.player_id;
+                    client.send(&ServerMessage::Welcome {
+                        view_distance,
+                        player_id,
+                        seed: preset.seed,
+                    });
+                    commands
+                        .entity(e)
+                        .insert(Transform::from_translation(client.position))
+                        .insert(Relative([view_distance as i32; 3]));
+                }
+                _ if !client.welcomed => {
+                    disconnect(&mut commands, e, &client, "Message sent before Hello");
+                    break;
+                }
+                ClientMessage::Move {
+                    translation,
+                    rotation,
+                } => {
+                    client.position = Vec3::from(translation);
+                    client.rotation = Quat::from_array(rotation).normalize();
+                }
+                ClientMessage::ViewDistance(view_distance) => {
+                    let view_distance = view_distance.min(settings.max_view_distance);
+                    commands
+                        .entity(e)
+                        .insert(Relative([view_distance as i32; 3]));
+                }
+                ClientMessage::EditBlock { pos, voxel } => {
+                    edits.send(BlockEditRequest {
+                        pos: PointN(pos),
+                        voxel: Voxel(voxel),
+                    });
+                }
+            }
+        }
+    }
+}
+
+/// Moves the viewer of each client to its last known position.
+fn follow_clients(mut clients: Query<(&RemoteClient, &mut Transform)>) {
+    for (client, mut transform) in clients.iter_mut() {
+        if transform.translation != client.position || transform.rotation != client.rotation {
+            transform.translation = client.position;
+            transform.rotation = client.rotation;
+        }
+    }
+}
+
+/// Sends every player the state of all the others.
+fn replicate_players(mut clients: Query<&mut RemoteClient>) {
+    let players: Vec<_> = clients
+        .iter()
+        .filter(|client| client.welcomed)
+        .map(|client| PlayerState {
+            id: client.player_id,
+            translation: client.position.to_array(),
+            rotation: client.rotation.to_array(),
+        })
+        .collect();
+    for mut client in clients.iter_mut().filter(|client| client.welcomed) {
+        let others = players
+            .iter()
+            .filter(|player| player.id != client.player_id)
+            .cloned()
+            .collect();
+        client.send(&ServerMessage::Players(others));
+    }
+}
+
+fn apply_edits(
+    mut edits: EventReader<BlockEditRequest>,
+    world: Res<ChunkWorld>,
+    blocks: Option<Res<Blocks>>,
+    block_assets: Option<Res<Assets<Block>>>,
+    mut chunks: Query<&mut Chunk>,
+    mut changes: EventWriter<VoxelChanged>,
+) {
+    // Servers without a block table take any edit.
+    let voxel_blocks = match (&blocks, &block_assets) {
+        (Some(blocks), Some(block_assets)) => Some(VoxelBlocks::new(blocks, block_assets)),
+        _ => None,
+    };
+    for edit in edits.iter() {
+        // The clients could not mesh blocks they do not know.
+        let valid = voxel_blocks
+            .as_ref()
+            .map_or(true, |voxel_blocks| voxel_blocks.is_valid(edit.voxel));
+        if !valid {
+            warn!(
+                "Edit of {:?} to invalid voxel {:?} dropped",
+                edit.pos, edit.voxel
+            );
+            continue;
+        }
+        let chunk = world
+            .world
+            .get(&voxel_chunk(edit.pos))
+            .and_then(|e| chunks.get_mut(*e).ok());
+        // Edits of chunks which are not generated yet are dropped.
+        if let Some(mut chunk) = chunk {
+            *chunk.data_mut().get_mut(edit.pos) = edit.voxel;
+            changes.send(VoxelChanged {
+                pos: edit.pos,
+                voxel: edit.voxel,
+            });
+        }
+    }
+}
+
+/// Sends the changed voxels, edited or not, to the clients which have their chunk.
+fn send_changes(mut changes: EventReader<VoxelChanged>, mut clients: Query<&mut RemoteClient>) {
+    for change in changes.iter() {
+        let chunk_pos = voxel_chunk(change.pos);
+        for mut client in clients.iter_mut() {
+            if client.sent.contains(&chunk_pos) {
+                client.send(&ServerMessage::BlockChanged {
+                    pos: change.pos.0,
+                    voxel: change.voxel.0,
+                });
+            }
+        }
+    }
+}
+
+/// Sends the blocks which started falling to the clients which have their chunk, which let them
+/// fall until the server tells them where they landed.
+fn send_falling_blocks(
+    falling: Query<(&FallingBlock, &Transform), Added<FallingBlock>>,
+    mut clients: Query<&mut RemoteClient>,
+) {
+    for (block, transform) in falling.iter() {
+        // Barely below the block it left, if it moved at all.
+        let pos = transform.translation.round().as_ivec3().to_array();
+        let chunk_pos = voxel_chunk(PointN(pos));
+        for mut client in clients.iter_mut() {
+            if client.sent.contains(&chunk_pos) {
+                client.send(&ServerMessage::BlockFell {
+                    pos,
+                    voxel: block.voxel.0,
+                });
+            }
+        }
+    }
+}
+
+/// Sends the closest missing chunks around each client and unloads the ones it left behind.
+fn stream_chunks(
+    settings: Res<ServerSettings>,
+    world: Res<ChunkWorld>,
+    chunks: Query<&Chunk>,
+    mut clients: Query<(&mut RemoteClient, &ChunkLocation, &Relative)>,
+) {
+    for (mut client, location, relative) in clients.iter_mut() {
+        let center = location.0;
+        let radius = PointN(relative.0);
+        let view = Extent3i::from_min_and_max(center - radius, center + radius);
+        // Chunks are kept a bit longer so moving back and forth does not resend them.
+        let keep = view.padded(1);
+
+        let left: Vec<_> = client
+            .sent
+            .iter()
+            .filter(|pos| !keep.contains(**pos))
+            .copied()
+            .collect();
+        for pos in left {
+            client.sent.remove(&pos);
+            client.send(&ServerMessage::UnloadChunk(pos.0));
+        }
+
+        let mut missing: Vec<_> = view
+            .iter_points()
+            .filter(|pos| !client.sent.contains(pos))
+            .filter_map(|pos| {
+                let chunk = chunks.get(*world.world.get(&pos)?).ok()?;
+                Some((pos, chunk))
+            })
+            .collect();
+        missing.sort_by_key(|(pos, _)| distance_squared(*pos, center));
+        for (pos, chunk) in missing.into_iter().take(settings.chunks_per_update) {
+            client.sent.insert(pos);
+            client.send(&ServerMessage::Chunk {
+                pos: pos.0,
+                voxels: CompressedVoxels::compress(chunk.data()),
+            });
+        }
+    }
+}
+
+fn distance_squared(a: Point3i, b: Point3i) -> i32 {
+    let [x, y, z] = (a - b).0;
+    x * x + y * y + z * z
+}
+
+fn flush(mut commands: Commands, mut clients: Query<(Entity, &mut RemoteClient)>) {
+    for (e, mut client) in clients.iter_mut() {
+        if let Err(error) = client.connection.flush() {
+            disconnect(&mut commands, e, &client, &error.to_string());
+        }
+    }
+}
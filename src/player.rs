@@ -0,0 +1,419 @@
+//! First-person movement, either flying through everything or walking on the solid blocks.
+
+use bevy::input::mouse::MouseMotion;
+use bevy::prelude::*;
+use bevy_fly_camera::FlyCamera;
+use building_blocks::core::{Extent3i, Point3i, PointN};
+
+use crate::blocks::{Block, Blocks, Fluid, VoxelBlocks};
+use crate::chunk::{get_voxel, Chunk, ChunkWorld, Voxel};
+use crate::AppState;
+
+/// Longer frames are simulated as this long, so that a hitch does not launch the player.
+const MAX_DELTA: f32 = 0.1;
+/// Degrees the camera can look up or down, short of straight where the yaw is lost.
+const MAX_PITCH: f32 = 89.9;
+
+pub struct PlayerSettings {
+    /// Width and depth of the body.
+    pub width: f32,
+    pub height: f32,
+    /// Height of the camera above the feet.
+    pub eye_height: f32,
+    pub walk_speed: f32,
+    pub gravity: f32,
+    pub max_fall_speed: f32,
+    pub jump_speed: f32,
+    /// Ledges up to this high are walked onto without jumping.
+    pub step_height: f32,
+    pub toggle_mode: KeyCode,
+    pub jump: KeyCode,
+}
+
+impl Default for PlayerSettings {
+    fn default() -> Self {
+        PlayerSettings {
+            width: 0.6,
+            height: 1.8,
+            eye_height: 1.6,
+            walk_speed: 4.5,
+            gravity: 28.0,
+            max_fall_speed: 50.0,
+            jump_speed: 8.5,
+            step_height: 1.0,
+            toggle_mode: KeyCode::F,
+            jump: KeyCode::Space,
+        }
+    }
+}
+
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub enum MovementMode {
+    /// Moved by the `FlyCamera`, without collisions.
+    Fly,
+    Walk,
+}
+
+/// Camera moved by the player, its `Transform` is at the eyes.
+#[derive(Component)]
+pub struct Player {
+    pub mode: MovementMode,
+    pub velocity: Vec3,
+    pub on_ground: bool,
+    /// Part of the body inside liquids, from 0 to 1.
+    pub submerged: f32,
+    /// Liquid most of the submerged part of the body is in.
+    pub fluid: Option<Fluid>,
+    /// Liquid the eyes are in.
+    pub eyes_in: Option<Fluid>,
+}
+
+impl Default for Player {
+    fn default() -> Self {
+        Player {
+            mode: MovementMode::Fly,
+            velocity: Vec3::ZERO,
+            on_ground: false,
+            submerged: 0.0,
+            fluid: None,
+            eyes_in: None,
+        }
+    }
+}
+
+/// Overlay tinting the screen while the eyes are in a liquid.
+#[derive(Component)]
+struct LiquidTint;
+
+pub struct PlayerPlugin;
+
+impl Plugin for PlayerPlugin {
+    fn build(&self, app: &mut App) {
+        app.init_resource::<PlayerSettings>()
+            .add_system_set(SystemSet::on_enter(AppState::Run).with_system(spawn_tint))
+            .add_system_set(
+                SystemSet::on_update(AppState::Run)
+                    .with_system(toggle_mode.label("toggle_mode"))
+                    .with_system(look.after("toggle_mode"))
+                    .with_system(find_liquids.label("find_liquids").after("toggle_mode"))
+                    .with_system(walk.label("walk").after("find_liquids"))
+                    .with_system(tint.after("walk")),
+            );
+    }
+}
+
+fn toggle_mode(
+    keys: Res<Input<KeyCode>>,
+    settings: Res<PlayerSettings>,
+    mut players: Query<(&mut Player, Option<&mut FlyCamera>)>,
+) {
+    if !keys.just_pressed(settings.toggle_mode) {
+        return;
+    }
+    for (mut player, fly_camera) in players.iter_mut() {
+        player.mode = match player.mode {
+            MovementMode::Fly => MovementMode::Walk,
+            MovementMode::Walk => MovementMode::Fly,
+        };
+        player.velocity = Vec3::ZERO;
+        player.on_ground = false;
+        info!("Movement mode {:?}", player.mode);
+        if let Some(mut fly_camera) = fly_camera {
+            fly_camera.enabled = player.mode == MovementMode::Fly;
+            fly_camera.velocity = Vec3::ZERO;
+        }
+    }
+}
+
+/// Turns the camera while walking, the `FlyCamera` does it while flying.
+fn look(
+    time: Res<Time>,
+    mut motion: EventReader<MouseMotion>,
+    mut players: Query<(&Player, &mut FlyCamera, &mut Transform)>,
+) {
+    let delta = motion
+        .iter()
+        .fold(Vec2::ZERO, |delta, motion| delta + motion.delta);
+    for (player, mut fly_camera, mut transform) in players.iter_mut() {
+        if player.mode != MovementMode::Walk {
+            continue;
+        }
+        // Same as the `FlyCamera`, so that switching modes keeps the orientation.
+        let sensitivity = fly_camera.sensitivity * time.delta_seconds();
+        fly_camera.yaw -= delta.x * sensitivity;
+        fly_camera.pitch = (fly_camera.pitch + delta.y * sensitivity).clamp(-MAX_PITCH, MAX_PITCH);
+        transform.rotation = Quat::from_axis_angle(Vec3::Y, fly_camera.yaw.to_radians())
+            * Quat::from_axis_angle(-Vec3::X, fly_camera.pitch.to_radians());
+    }
+}
+
+/// Measures how much of each player is in liquids.
+fn find_liquids(
+    settings: Res<PlayerSettings>,
+    world: Res<ChunkWorld>,
+    chunks: Query<&Chunk>,
+    blocks: Res<Blocks>,
+    block_assets: Res<Assets<Block>>,
+    mut players: Query<(&mut Player, &Transform)>,
+) {
+    let voxel_blocks = VoxelBlocks::new(&blocks, &block_assets);
+    for (mut player, transform) in players.iter_mut() {
+        let feet = transform.translation - Vec3::Y * settings.eye_height;
+        let body = Aabb::from_feet(feet, settings.width, settings.height);
+        // Volume of the body in each liquid, by block id plus one.
+        let mut volumes = [0.0; 32];
+        for pos in body.blocks() {
+            if let Some(voxel) = get_voxel(&world, &chunks, pos) {
+                if voxel_blocks.fluid(voxel).is_some() {
+                    volumes[voxel.block() as usize] += body.overlap(pos);
+                }
+            }
+        }
+        let (block, _) = volumes
+            .iter()
+            .enumerate()
+            .fold((0, 0.0), |max, (block, volume)| {
+                if *volume > max.1 {
+                    (block, *volume)
+                } else {
+                    max
+                }
+            });
+        player.submerged = (volumes.iter().sum::<f32>() / body.volume()).min(1.0);
+        player.fluid = voxel_blocks.fluid(Voxel::new(block as u8, 0)).cloned();
+        player.eyes_in = get_voxel(&world, &chunks, block_pos(transform.translation))
+            .and_then(|voxel| voxel_blocks.fluid(voxel).cloned());
+    }
+}
+
+#[allow(clippy::too_many_arguments)]
+fn walk(
+    time: Res<Time>,
+    keys: Res<Input<KeyCode>>,
+    settings: Res<PlayerSettings>,
+    world: Res<ChunkWorld>,
+    chunks: Query<&Chunk>,
+    blocks: Res<Blocks>,
+    block_assets: Res<Assets<Block>>,
+    mut players: Query<(&mut Player, &mut Transform)>,
+) {
+    let voxel_blocks = VoxelBlocks::new(&blocks, &block_assets);
+    // Chunks which are not there yet are walls, so that nobody falls out of the world.
+    let solid = |pos: Point3i| {
+        get_voxel(&world, &chunks, pos).map_or(true, |voxel| voxel_blocks.is_solid(voxel))
+    };
+    let dt = time.delta_seconds().min(MAX_DELTA);
+
+    for (mut player, mut transform) in players.iter_mut() {
+        if player.mode != MovementMode::Walk {
+            continue;
+        }
+        let feet = transform.translation - Vec3::Y * settings.eye_height;
+        // The player is frozen until the chunk around it arrives.
+        if get_voxel(&world, &chunks, block_pos(feet)).is_none() {
+            continue;
+        }
+
+        let forward = (transform.rotation * -Vec3::Z) * Vec3::new(1.0, 0.0, 1.0);
+        let right = (transform.rotation * Vec3::X) * Vec3::new(1.0, 0.0, 1.0);
+        let mut direction = Vec3::ZERO;
+        for (key, axis) in [
+            (KeyCode::W, forward),
+            (KeyCode::S, -forward),
+            (KeyCode::D, right),
+            (KeyCode::A, -right),
+        ] {
+            if keys.pressed(key) {
+                direction += axis.normalize_or_zero();
+            }
+        }
+        let submerged = player.submerged;
+        let fluid = player.fluid.clone();
+        let speed = match &fluid {
+            Some(fluid) => settings.walk_speed * (1.0 + (fluid.speed - 1.0) * submerged),
+            None => settings.walk_speed,
+        };
+        let horizontal = direction.normalize_or_zero() * speed;
+        player.velocity.x = horizontal.x;
+        player.velocity.z = horizontal.z;
+        if player.on_ground && keys.pressed(settings.jump) {
+            player.velocity.y = settings.jump_speed;
+        }
+        if let Some(fluid) = &fluid {
+            if keys.pressed(settings.jump) {
+                player.velocity.y = player.velocity.y.max(fluid.swim_speed);
+            }
+            let buoyancy = settings.gravity * fluid.buoyancy * submerged;
+            player.velocity.y += buoyancy * dt;
+            player.velocity.y *= (-fluid.drag * submerged * dt).exp();
+        }
+        player.velocity.y =
+            (player.velocity.y - settings.gravity * dt).max(-settings.max_fall_speed);
+
+        let step = player.velocity * dt;
+        let start = Aabb::from_feet(feet, settings.width, settings.height);
+        let mut body = start;
+        let slid = body.slide(step.x, step.z, &solid);
+        if (player.on_ground || fluid.is_some()) && slid != Vec2::new(step.x, step.z) {
+            // Blocked while on the ground or swimming, climb the ledge if it is low enough.
+            let mut stepped = start;
+            let up = stepped.sweep(1, settings.step_height, &solid);
+            let stepped_slid = stepped.slide(step.x, step.z, &solid);
+            stepped.sweep(1, -up, &solid);
+            if stepped_slid.length_squared() > slid.length_squared() {
+                body = stepped;
+            }
+        }
+        let fallen = body.sweep(1, step.y, &solid);
+        if fallen != step.y {
+            player.on_ground = step.y < 0.0;
+            player.velocity.y = 0.0;
+        } else {
+            player.on_ground = false;
+        }
+
+        transform.translation = body.feet() + Vec3::Y * settings.eye_height;
+    }
+}
+
+fn spawn_tint(mut commands: Commands) {
+    commands.spawn_bundle(UiCameraBundle::default());
+    commands
+        .spawn_bundle(NodeBundle {
+            style: Style {
+                position_type: PositionType::Absolute,
+                size: Size::new(Val::Percent(100.0), Val::Percent(100.0)),
+                ..Default::default()
+            },
+            color: UiColor(Color::NONE),
+            visibility: Visibility { is_visible: false },
+            ..Default::default()
+        })
+        .insert(LiquidTint);
+}
+
+/// Tints the screen with the liquid the eyes are in, which also hides what is far away.
+fn tint(
+    players: Query<&Player>,
+    mut overlays: Query<(&mut UiColor, &mut Visibility), With<LiquidTint>>,
+    mut clear_color: ResMut<ClearColor>,
+    mut dry_clear_color: Local<Option<Color>>,
+) {
+    let fluid = players.iter().find_map(|player| player.eyes_in.as_ref());
+    for (mut color, mut visibility) in overlays.iter_mut() {
+        visibility.is_visible = fluid.is_some();
+        if let Some(fluid) = fluid {
+            let (r, g, b, a) = fluid.tint;
+            color.0 = Color::rgba(r, g, b, a);
+        }
+    }
+    match (fluid, dry_clear_color.is_some()) {
+        (Some(fluid), false) => {
+            let (r, g, b, _) = fluid.tint;
+            *dry_clear_color = Some(clear_color.0);
+            clear_color.0 = Color::rgb(r, g, b);
+        }
+        (None, true) => clear_color.0 = dry_clear_color.take().unwrap(),
+        _ => {}
+    }
+}
+
+fn block_pos(pos: Vec3) -> Point3i {
+    PointN(pos.floor().as_ivec3().to_array())
+}
+
+/// Keeps faces resting exactly on a block boundary from overlapping the block.
+const EPSILON: f32 = 1e-4;
+
+/// Axis aligned box of the body in world space.
+#[derive(Clone, Copy, Debug)]
+struct Aabb {
+    min: Vec3,
+    max: Vec3,
+}
+
+impl Aabb {
+    fn from_feet(feet: Vec3, width: f32, height: f32) -> Self {
+        let half = Vec3::new(width / 2.0, 0.0, width / 2.0);
+        Aabb {
+            min: feet - half,
+            max: feet + half + Vec3::Y * height,
+        }
+    }
+
+    fn volume(&self) -> f32 {
+        let size = self.max - self.min;
+        size.x * size.y * size.z
+    }
+
+    /// Volume shared with the block at `pos`.
+    fn overlap(&self, pos: Point3i) -> f32 {
+        let min = Vec3::new(pos.x() as f32, pos.y() as f32, pos.z() as f32);
+        let size = (self.max.min(min + Vec3::ONE) - self.min.max(min)).max(Vec3::ZERO);
+        size.x * size.y * size.z
+    }
+
+    /// Blocks the box overlaps.
+    fn blocks(&self) -> Vec<Point3i> {
+        Extent3i::from_min_and_max(block_pos(self.min), block_pos(self.max))
+            .iter_points()
+            .collect()
+    }
+
+    fn feet(&self) -> Vec3 {
+        Vec3::new(
+            (self.min.x + self.max.x) / 2.0,
+            self.min.y,
+            (self.min.z + self.max.z) / 2.0,
+        )
+    }
+
+    /// Moves horizontally, each axis stops at the first solid block. Returns how far it moved.
+    fn slide(&mut self, x: f32, z: f32, solid: &impl Fn(Point3i) -> bool) -> Vec2 {
+        Vec2::new(self.sweep(0, x, solid), self.sweep(2, z, solid))
+    }
+
+    /// Moves by `delta` along `axis` until touching a solid block, returns how far it moved.
+    ///
+    /// Blocks are checked layer by layer, so fast moves cannot tunnel through them.
+    fn sweep(&mut self, axis: usize, delta: f32, solid: &impl Fn(Point3i) -> bool) -> f32 {
+        let (a, b) = ((axis + 1) % 3, (axis + 2) % 3);
+        let range =
+            |min: f32, max: f32| (min + EPSILON).floor() as i32..=(max - EPSILON).ceil() as i32 - 1;
+        let layer_is_solid = |layer: i32| {
+            range(self.min[a], self.max[a]).any(|i| {
+                range(self.min[b], self.max[b]).any(|j| {
+                    let mut pos = [0; 3];
+                    pos[axis] = layer;
+                    pos[a] = i;
+                    pos[b] = j;
+                    solid(PointN(pos))
+                })
+            })
+        };
+
+        let mut moved = delta;
+        if delta > 0.0 {
+            let mut layer = (self.max[axis] - EPSILON).ceil() as i32;
+            while (layer as f32) < self.max[axis] + delta {
+                if layer_is_solid(layer) {
+                    moved = (layer as f32 - self.max[axis]).max(0.0);
+                    break;
+                }
+                layer += 1;
+            }
+        } else if delta < 0.0 {
+            let mut layer = (self.min[axis] + EPSILON).floor() as i32 - 1;
+            while (layer + 1) as f32 > self.min[axis] + delta {
+                if layer_is_solid(layer) {
+                    moved = ((layer + 1) as f32 - self.min[axis]).min(0.0);
+                    break;
+                }
+                layer -= 1;
+            }
+        }
+        self.min[axis] += moved;
+        self.max[axis] += moved;
+        moved
+    }
+}
@@ -1,35 +1,271 @@
 use std::default::Default;
 use std::f32::consts::PI;
 
+use bevy::ecs::system::lifetimeless::SRes;
+use bevy::ecs::system::SystemParamItem;
+use bevy::pbr::{Material, MaterialMeshBundle, MaterialPipeline, MaterialPlugin, NotShadowCaster};
 use bevy::prelude::*;
+use bevy::reflect::TypeUuid;
 use bevy::render::mesh::VertexAttributeValues;
+use bevy::render::render_asset::{PrepareAssetError, RenderAsset};
+use bevy::render::render_resource::std140::{AsStd140, Std140};
+use bevy::render::render_resource::{
+    BindGroup, BindGroupDescriptor, BindGroupEntry, BindGroupLayout, BindGroupLayoutDescriptor,
+    BindGroupLayoutEntry, BindingType, Buffer, BufferBindingType, BufferInitDescriptor, BufferSize,
+    BufferUsages, ShaderStages,
+};
+use bevy::render::renderer::RenderDevice;
 
+use crate::chunk::ChunkMaterial;
 use crate::AppState;
 
+/// Colours of the sky straight up and at the horizon, by day and at night.
+const DAY_ZENITH: [f32; 3] = [0.18, 0.4, 0.9];
+const DAY_HORIZON: [f32; 3] = [0.62, 0.8, 0.95];
+const NIGHT_ZENITH: [f32; 3] = [0.005, 0.007, 0.02];
+const NIGHT_HORIZON: [f32; 3] = [0.03, 0.04, 0.08];
+/// Tint of the sky around the sun when it rises and sets.
+const SUNRISE_COLOR: [f32; 3] = [1.0, 0.42, 0.12];
+/// Illuminance of the sun high in the sky and of the full moon.
+const SUN_ILLUMINANCE: f32 = 250_000.0;
+const MOON_ILLUMINANCE: f32 = 10_000.0;
+const SUNSET_COLOR: [f32; 3] = [1.0, 0.55, 0.3];
+const MOON_COLOR: [f32; 3] = [0.6, 0.7, 1.0];
+/// Brightness of the ambient light by day, the default of bevy.
+const AMBIENT_BRIGHTNESS: f32 = 0.05;
+/// Part of the sky light left at night.
+const NIGHT_LIGHT: f32 = 0.15;
+/// Angle between the path of the sun and the vertical, so it is not right above at noon.
+const TILT: f32 = 0.4;
+
+/// Time of day, in fractions of a day from midnight: 0.25 at sunrise, 0.5 at noon and 0.75 at
+/// sunset.
+///
+/// The sun rises in the east, towards +X, and sets in the west, the moon is on the other side.
+pub struct TimeOfDay {
+    time: f32,
+    /// Seconds a day lasts.
+    pub day_length: f32,
+    pub paused: bool,
+}
+
+impl Default for TimeOfDay {
+    fn default() -> Self {
+        TimeOfDay {
+            time: 0.3,
+            day_length: 1200.0,
+            paused: false,
+        }
+    }
+}
+
+impl TimeOfDay {
+    pub fn time(&self) -> f32 {
+        self.time
+    }
+
+    pub fn set_time(&mut self, time: f32) {
+        self.time = time.rem_euclid(1.0);
+    }
+
+    /// Direction of the sun from the world.
+    pub fn sun_direction(&self) -> Vec3 {
+        let angle = self.time * 2.0 * PI;
+        Quat::from_rotation_x(TILT) * Vec3::new(angle.sin(), -angle.cos(), 0.0)
+    }
+
+    pub fn moon_direction(&self) -> Vec3 {
+        -self.sun_direction()
+    }
+
+    /// How much the sun lights the world, from zero at night to one by day, rising through the
+    /// twilight.
+    pub fn daylight(&self) -> f32 {
+        smoothstep(-0.1, 0.2, self.sun_direction().y)
+    }
+
+    /// Part of the sky light reaching the world, from `NIGHT_LIGHT` at night to one by day.
+    pub fn sky_light(&self) -> f32 {
+        NIGHT_LIGHT + (1.0 - NIGHT_LIGHT) * self.daylight()
+    }
+
+    pub fn zenith_color(&self) -> Color {
+        self.sky_color(NIGHT_ZENITH, DAY_ZENITH)
+    }
+
+    pub fn horizon_color(&self) -> Color {
+        self.sky_color(NIGHT_HORIZON, DAY_HORIZON)
+    }
+
+    fn sky_color(&self, night: [f32; 3], day: [f32; 3]) -> Color {
+        let daylight = self.daylight();
+        let [r, g, b] = [0, 1, 2].map(|i| night[i] + (day[i] - night[i]) * daylight);
+        Color::rgb_linear(r, g, b)
+    }
+}
+
+fn smoothstep(edge0: f32, edge1: f32, x: f32) -> f32 {
+    let x = ((x - edge0) / (edge1 - edge0)).clamp(0.0, 1.0);
+    x * x * (3.0 - 2.0 * x)
+}
+
+/// Sky sphere, following the time of day.
+#[derive(Component)]
+struct Sky(Handle<SkyMaterial>);
+
+/// Sky going from the horizon colour up to the zenith colour, with the sun, its glow when it
+/// rises and sets, and the stars at night.
+#[derive(Debug, Clone, TypeUuid)]
+#[uuid = "8b0f5e2a-93c4-4d7e-b1a6-5c2e0f7d4a19"]
+pub struct SkyMaterial {
+    pub zenith: Color,
+    pub horizon: Color,
+    /// Colour of the glow around the sun, black when it is high.
+    pub sunrise: Color,
+    pub sun_color: Color,
+    /// Direction of the sun from the world.
+    pub sun_direction: Vec3,
+    /// How much the stars show, from zero by day to one at night.
+    pub stars: f32,
+    /// Colour hiding the sky, its alpha is how much, for the fog in liquids.
+    pub fog: Color,
+}
+
+impl SkyMaterial {
+    fn new(time_of_day: &TimeOfDay) -> Self {
+        let sun = time_of_day.sun_direction();
+        // The glow is brightest with the sun right on the horizon.
+        let glow = 1.0 - smoothstep(0.0, 0.35, sun.y.abs());
+        let [r, g, b] = SUNRISE_COLOR.map(|channel| channel * glow);
+        let (_, light) = celestial_light(time_of_day);
+        SkyMaterial {
+            zenith: time_of_day.zenith_color(),
+            horizon: time_of_day.horizon_color(),
+            sunrise: Color::rgb_linear(r, g, b),
+            sun_color: light.color,
+            sun_direction: sun,
+            stars: 1.0 - time_of_day.daylight(),
+            fog: Color::NONE,
+        }
+    }
+}
+
+#[derive(Clone, AsStd140)]
+struct SkyMaterialUniformData {
+    zenith: Vec4,
+    horizon: Vec4,
+    sunrise: Vec4,
+    sun_color: Vec4,
+    sun_direction: Vec3,
+    stars: f32,
+    fog: Vec4,
+}
+
+#[derive(Clone)]
+pub struct GpuSkyMaterial {
+    _buffer: Buffer,
+    bind_group: BindGroup,
+}
+
+impl RenderAsset for SkyMaterial {
+    type ExtractedAsset = SkyMaterial;
+    type PreparedAsset = GpuSkyMaterial;
+    type Param = (SRes<RenderDevice>, SRes<MaterialPipeline<Self>>);
+
+    fn extract_asset(&self) -> Self::ExtractedAsset {
+        self.clone()
+    }
+
+    fn prepare_asset(
+        material: Self::ExtractedAsset,
+        (render_device, pipeline): &mut SystemParamItem<Self::Param>,
+    ) -> Result<Self::PreparedAsset, PrepareAssetError<Self::ExtractedAsset>> {
+        let value = SkyMaterialUniformData {
+            zenith: material.zenith.as_linear_rgba_f32().into(),
+            horizon: material.horizon.as_linear_rgba_f32().into(),
+            sunrise: material.sunrise.as_linear_rgba_f32().into(),
+            sun_color: material.sun_color.as_linear_rgba_f32().into(),
+            sun_direction: material.sun_direction,
+            stars: material.stars,
+            fog: material.fog.as_linear_rgba_f32().into(),
+        };
+        let buffer = render_device.create_buffer_with_data(&BufferInitDescriptor {
+            label: Some("sky_material_uniform_buffer"),
+            usage: BufferUsages::UNIFORM | BufferUsages::COPY_DST,
+            contents: value.as_std140().as_bytes(),
+        });
+        let bind_group = render_device.create_bind_group(&BindGroupDescriptor {
+            entries: &[BindGroupEntry {
+                binding: 0,
+                resource: buffer.as_entire_binding(),
+            }],
+            label: Some("sky_material_bind_group"),
+            layout: &pipeline.material_layout,
+        });
+        Ok(GpuSkyMaterial {
+            _buffer: buffer,
+            bind_group,
+        })
+    }
+}
+
+impl Material for SkyMaterial {
+    fn bind_group(material: &<Self as RenderAsset>::PreparedAsset) -> &BindGroup {
+        &material.bind_group
+    }
+
+    fn bind_group_layout(render_device: &RenderDevice) -> BindGroupLayout {
+        render_device.create_bind_group_layout(&BindGroupLayoutDescriptor {
+            entries: &[BindGroupLayoutEntry {
+                binding: 0,
+                visibility: ShaderStages::FRAGMENT,
+                ty: BindingType::Buffer {
+                    ty: BufferBindingType::Uniform,
+                    has_dynamic_offset: false,
+                    min_binding_size: BufferSize::new(
+                        SkyMaterialUniformData::std140_size_static() as u64
+                    ),
+                },
+                count: None,
+            }],
+            label: Some("sky_material_layout"),
+        })
+    }
+
+    fn vertex_shader(asset_server: &AssetServer) -> Option<Handle<Shader>> {
+        Some(asset_server.load("shaders/sky.wgsl"))
+    }
+
+    fn fragment_shader(asset_server: &AssetServer) -> Option<Handle<Shader>> {
+        Some(asset_server.load("shaders/sky.wgsl"))
+    }
+}
+
+/// Turns the sun and the moon around the world following `TimeOfDay`, which can be inserted
+/// before to start at another time of day.
 pub struct SkyPlugin;
 
 impl Plugin for SkyPlugin {
     fn build(&self, app: &mut App) {
-        app.add_system_set(SystemSet::on_enter(AppState::Run).with_system(setup))
-            .add_system_set(SystemSet::on_update(AppState::Run).with_system(move_light));
+        app.init_resource::<TimeOfDay>()
+            .add_plugin(MaterialPlugin::<SkyMaterial>::default())
+            .add_system_set(SystemSet::on_enter(AppState::Run).with_system(setup))
+            .add_system_set(
+                SystemSet::on_update(AppState::Run)
+                    .with_system(advance_time.label("time_of_day"))
+                    .with_system(move_light.after("time_of_day"))
+                    .with_system(dim_chunks.after("time_of_day")),
+            );
     }
 }
 
 fn setup(
     mut commands: Commands,
+    time_of_day: Res<TimeOfDay>,
     mut meshes: ResMut<Assets<Mesh>>,
-    mut materials: ResMut<Assets<StandardMaterial>>,
+    mut materials: ResMut<Assets<SkyMaterial>>,
 ) {
-    let sky_color = materials.add(StandardMaterial {
-        base_color: Color::Rgba {
-            red: 0.443137,
-            blue: 0.737255,
-            green: 0.882353,
-            alpha: 1.0,
-        },
-        unlit: true,
-        ..Default::default()
-    });
+    let sky_material = materials.add(SkyMaterial::new(&time_of_day));
     let mut mesh = Mesh::from(shape::Icosphere {
         radius: 999.0,
         ..Default::default()
@@ -45,36 +281,94 @@ fn setup(
     }
     let sphere = meshes.add(mesh);
 
-    commands.spawn_bundle(PbrBundle {
-        mesh: sphere,
-        material: sky_color,
-        ..Default::default()
-    });
-    // spawn sun ?:
-    commands.spawn_bundle(DirectionalLightBundle {
-        directional_light: DirectionalLight {
-            shadows_enabled: true,
-            illuminance: 250_000.0,
-            color: Color::from([1.0, 1.0, 1.0]),
+    commands
+        .spawn_bundle(MaterialMeshBundle {
+            mesh: sphere,
+            material: sky_material.clone(),
             ..Default::default()
-        },
+        })
+        .insert_bundle((Sky(sky_material), NotShadowCaster));
+    // The sun by day and the moon at night, a single directional light is rendered.
+    let (transform, directional_light) = celestial_light(&time_of_day);
+    commands.spawn_bundle(DirectionalLightBundle {
+        directional_light,
+        transform,
         ..Default::default()
     });
 }
 
-fn recalc(r: f32, theta: f32, fi: f32) -> [f32; 3] {
-    [
-        r * theta.sin() * fi.cos(),
-        r * theta.sin() * fi.sin(),
-        r * theta.cos(),
-    ]
+fn advance_time(time: Res<Time>, mut time_of_day: ResMut<TimeOfDay>) {
+    if time_of_day.paused {
+        return;
+    }
+    let day = time_of_day.time + time.delta_seconds() / time_of_day.day_length;
+    time_of_day.set_time(day);
+}
+
+fn move_light(
+    time_of_day: Res<TimeOfDay>,
+    mut ambient: ResMut<AmbientLight>,
+    mut materials: ResMut<Assets<SkyMaterial>>,
+    mut lights: Query<(&mut Transform, &mut DirectionalLight)>,
+    sky: Query<&Sky>,
+) {
+    if !time_of_day.is_changed() {
+        return;
+    }
+    for (mut transform, mut light) in lights.iter_mut() {
+        let (new_transform, new_light) = celestial_light(&time_of_day);
+        *transform = new_transform;
+        *light = new_light;
+    }
+    ambient.brightness = AMBIENT_BRIGHTNESS * time_of_day.sky_light();
+    for Sky(material) in sky.iter() {
+        if let Some(material) = materials.get_mut(material) {
+            *material = SkyMaterial {
+                fog: material.fog,
+                ..SkyMaterial::new(&time_of_day)
+            };
+        }
+    }
 }
 
-fn move_light(mut query: Query<&mut Transform, With<DirectionalLight>>, time: Res<Time>) {
-    for mut t in query.iter_mut() {
-        *t = Transform::from_translation(
-            recalc(10.0, time.seconds_since_startup() as f32, PI / 2.0).into(),
+/// Light of the sun by day and of the moon at night, fading out as they set.
+fn celestial_light(time_of_day: &TimeOfDay) -> (Transform, DirectionalLight) {
+    let sun = time_of_day.sun_direction();
+    let (direction, illuminance, color) = if sun.y > 0.0 {
+        // The sun is redder close to the horizon.
+        let white = smoothstep(0.0, 0.3, sun.y);
+        let color = SUNSET_COLOR.map(|channel| channel + (1.0 - channel) * white);
+        (sun, SUN_ILLUMINANCE * smoothstep(0.0, 0.2, sun.y), color)
+    } else {
+        let moon = time_of_day.moon_direction();
+        (
+            moon,
+            MOON_ILLUMINANCE * smoothstep(0.0, 0.2, moon.y),
+            MOON_COLOR,
         )
-        .looking_at(Vec3::ZERO, Vec3::Y);
+    };
+    let transform = Transform::from_translation(direction * 10.0).looking_at(Vec3::ZERO, Vec3::Y);
+    let light = DirectionalLight {
+        shadows_enabled: true,
+        illuminance,
+        color: Color::from(color),
+        ..Default::default()
+    };
+    (transform, light)
+}
+
+/// Dims the sky light of the chunks, their materials are only prepared again when it changes
+/// visibly.
+fn dim_chunks(time_of_day: Res<TimeOfDay>, mut materials: ResMut<Assets<ChunkMaterial>>) {
+    let daylight = time_of_day.sky_light();
+    let dimmed: Vec<_> = materials
+        .iter()
+        .filter(|(_, material)| (material.daylight - daylight).abs() > 1.0 / 256.0)
+        .map(|(id, _)| id)
+        .collect();
+    for id in dimmed {
+        if let Some(material) = materials.get_mut(id) {
+            material.daylight = daylight;
+        }
     }
 }
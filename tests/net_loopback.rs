@@ -0,0 +1,247 @@
+//! Runs a server and clients in one process and checks that the clients end up with the
+//! server's chunks, edits and players.
+
+use std::net::{Ipv4Addr, SocketAddr};
+use std::thread;
+use std::time::{Duration, Instant};
+
+use bevy::app::Events;
+use bevy::prelude::*;
+use building_blocks::core::{Extent3i, Point3i, PointN};
+use building_blocks::prelude::{Array3x1, Get, GetMut};
+
+use rusted_terra::blocks::Blocks;
+use rusted_terra::chunk::{
+    chunk_extent, Chunk, ChunkGeneratorPlugin, ChunkPlugin, ChunkWorld, Relative, Voxel,
+    WorldPreset,
+};
+use rusted_terra::net::{
+    BlockEditRequest, Client, ClientMessage, ClientPlugin, ClientSettings, CompressedVoxels,
+    Connection, RemotePlayer, Server, ServerMessage, ServerPlugin, ServerSettings,
+    PROTOCOL_VERSION,
+};
+use rusted_terra::AppState;
+
+const TIMEOUT: Duration = Duration::from_secs(300);
+/// Not the default seed, to tell that clients are told the seed of the server.
+const SEED: i64 = 42;
+
+fn server() -> (App, SocketAddr) {
+    let mut app = App::new();
+    app.add_plugins(MinimalPlugins)
+        .add_state(AppState::Run)
+        .insert_resource(ServerSettings {
+            address: (Ipv4Addr::LOCALHOST, 0).into(),
+            ..Default::default()
+        })
+        .insert_resource(WorldPreset {
+            seed: SEED,
+            ..Default::default()
+        })
+        .add_plugin(ChunkPlugin)
+        .add_plugin(ChunkGeneratorPlugin)
+        .add_plugin(ServerPlugin);
+    app.update();
+    let address = app.world.get_resource::<Server>().unwrap().local_addr();
+    (app, address)
+}
+
+fn client(server: SocketAddr, translation: Vec3) -> App {
+    let mut app = App::new();
+    app.add_plugins(MinimalPlugins)
+        .add_state(AppState::Run)
+        .insert_resource(ClientSettings {
+            server,
+            view_distance: 1,
+        })
+        .add_plugin(ChunkPlugin)
+        .add_plugin(ClientPlugin);
+    app.world
+        .spawn()
+        .insert(Transform::from_translation(translation))
+        .insert(Relative([1; 3]));
+    app
+}
+
+/// Updates both apps until `done` holds for the client.
+fn run_until(server: &mut App, client: &mut App, done: impl Fn(&mut App) -> bool) {
+    let start = Instant::now();
+    while !done(client) {
+        assert!(start.elapsed() < TIMEOUT, "Timed out");
+        server.update();
+        client.update();
+        thread::sleep(Duration::from_millis(1));
+    }
+}
+
+fn chunk_data(app: &mut App, pos: Point3i) -> Option<Array3x1<Voxel>> {
+    let e = *app.world.get_resource::<ChunkWorld>()?.world.get(&pos)?;
+    app.world.get::<Chunk>(e).map(|chunk| chunk.data().clone())
+}
+
+fn same_voxels(a: &Array3x1<Voxel>, b: &Array3x1<Voxel>) -> bool {
+    a.extent() == b.extent()
+        && a.extent()
+            .iter_points()
+            .all(|pos| a.get(pos).0 == b.get(pos).0)
+}
+
+#[test]
+fn compressed_voxels_round_trip() {
+    let extent = chunk_extent(PointN([1, -2, 3]));
+    let mut data = Array3x1::fill(extent, Voxel(0));
+    for pos in extent.iter_points() {
+        if pos.y() < -50 || (pos.x() + pos.z()) % 7 == 0 {
+            *data.get_mut(pos) = Voxel((pos.y().rem_euclid(5)) as u8);
+        }
+    }
+    let compressed = CompressedVoxels::compress(&data);
+    let decompressed = compressed.decompress(extent).unwrap();
+    assert!(same_voxels(&data, &decompressed));
+    assert!(compressed
+        .decompress(chunk_extent(PointN([0; 3])).padded(1))
+        .is_err());
+}
+
+#[test]
+fn client_receives_chunks_and_edits() {
+    let (mut server, address) = server();
+    let mut client = client(address, Vec3::new(16.0, 48.0, 16.0));
+
+    let center = PointN([0, 1, 0]);
+    let positions: Vec<Point3i> =
+        Extent3i::from_min_and_max(center - PointN([1; 3]), center + PointN([1; 3]))
+            .iter_points()
+            .collect();
+    run_until(&mut server, &mut client, |client| {
+        positions
+            .iter()
+            .all(|pos| chunk_data(client, *pos).is_some())
+    });
+    assert_eq!(
+        client.world.get_resource::<WorldPreset>().unwrap().seed,
+        SEED
+    );
+    for pos in positions.iter() {
+        let sent = chunk_data(&mut server, *pos).unwrap();
+        let received = chunk_data(&mut client, *pos).unwrap();
+        assert!(same_voxels(&sent, &received), "Chunk {:?} differs", pos);
+    }
+
+    let pos = PointN([3, 40, 5]);
+    let voxel = chunk_data(&mut client, center).unwrap().get(pos).0;
+    let edited = if voxel == 2 { 1 } else { 2 };
+    client
+        .world
+        .get_resource_mut::<Events<BlockEditRequest>>()
+        .unwrap()
+        .send(BlockEditRequest {
+            pos,
+            voxel: Voxel(edited),
+        });
+    run_until(&mut server, &mut client, |client| {
+        chunk_data(client, center).unwrap().get(pos).0 == edited
+    });
+    assert_eq!(chunk_data(&mut server, center).unwrap().get(pos).0, edited);
+}
+
+#[test]
+fn servers_without_a_block_table_take_any_edit() {
+    let (mut server, address) = server();
+    assert!(server.world.get_resource::<Blocks>().is_none());
+    let mut client = client(address, Vec3::new(16.0, 48.0, 16.0));
+
+    let center = PointN([0, 1, 0]);
+    run_until(&mut server, &mut client, |client| {
+        chunk_data(client, center).is_some()
+    });
+    // No block table knows this voxel.
+    let pos = PointN([7, 36, 9]);
+    let edited = 200;
+    client
+        .world
+        .get_resource_mut::<Events<BlockEditRequest>>()
+        .unwrap()
+        .send(BlockEditRequest {
+            pos,
+            voxel: Voxel(edited),
+        });
+    run_until(&mut server, &mut client, |client| {
+        chunk_data(client, center).unwrap().get(pos).0 == edited
+    });
+    assert_eq!(chunk_data(&mut server, center).unwrap().get(pos).0, edited);
+}
+
+#[test]
+fn other_protocol_versions_are_rejected() {
+    let (mut server, address) = server();
+    let mut connection = Connection::connect(address).unwrap();
+    connection.send(&ClientMessage::Hello {
+        version: PROTOCOL_VERSION + 1,
+        view_distance: 1,
+    });
+    connection.flush().unwrap();
+
+    let start = Instant::now();
+    let message = loop {
+        assert!(start.elapsed() < TIMEOUT, "Timed out");
+        server.update();
+        if let Some(message) = connection.receive::<ServerMessage>().unwrap() {
+            break message;
+        }
+        thread::sleep(Duration::from_millis(1));
+    };
+    assert!(matches!(message, ServerMessage::Rejected(_)));
+}
+
+/// Where `app` shows the players other than itself.
+fn remote_players(app: &mut App) -> Vec<(u32, Vec3)> {
+    app.world
+        .query::<(&RemotePlayer, &Transform)>()
+        .iter(&app.world)
+        .map(|(player, transform)| (player.id(), transform.translation))
+        .collect()
+}
+
+fn player_id(app: &App) -> Option<u32> {
+    app.world.get_resource::<Client>()?.player_id()
+}
+
+#[test]
+fn players_see_each_other() {
+    let (mut server, address) = server();
+    let a_position = Vec3::new(16.0, 48.0, 16.0);
+    let b_position = Vec3::new(-20.0, 40.0, 8.0);
+    let mut a = client(address, a_position);
+    let mut b = client(address, b_position);
+
+    let close = |players: &[(u32, Vec3)], id: Option<u32>, position: Vec3| {
+        players.len() == 1 && Some(players[0].0) == id && players[0].1.distance(position) < 0.01
+    };
+    let start = Instant::now();
+    loop {
+        assert!(start.elapsed() < TIMEOUT, "Timed out");
+        server.update();
+        a.update();
+        b.update();
+        let (a_id, b_id) = (player_id(&a), player_id(&b));
+        if a_id.is_some()
+            && a_id != b_id
+            && close(&remote_players(&mut a), b_id, b_position)
+            && close(&remote_players(&mut b), a_id, a_position)
+        {
+            break;
+        }
+        thread::sleep(Duration::from_millis(1));
+    }
+
+    // Players which leave disappear from the others.
+    drop(b);
+    let start = Instant::now();
+    while !remote_players(&mut a).is_empty() {
+        assert!(start.elapsed() < TIMEOUT, "Timed out");
+        server.update();
+        a.update();
+        thread::sleep(Duration::from_millis(1));
+    }
+}
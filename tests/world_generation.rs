@@ -0,0 +1,148 @@
+//! Generates a few chunks headlessly and compares their voxels against golden hashes,
+//! so changes to the facets can't silently change the world.
+//!
+//! Run with `BLESS=1` to write the golden hashes, to accept intended changes or when the
+//! golden file does not exist yet.
+
+use std::collections::BTreeMap;
+use std::fs;
+use std::path::PathBuf;
+use std::thread;
+use std::time::{Duration, Instant};
+
+use bevy::prelude::*;
+use building_blocks::core::{Point3i, PointN};
+use building_blocks::prelude::Get;
+
+use rusted_terra::chunk::{Chunk, ChunkGeneratorPlugin, GeneratingArea, WorldPreset};
+
+const SEED: i64 = 124235;
+
+const CHUNKS: [[i32; 3]; 8] = [
+    [0, 0, 0],
+    [0, 1, 0],
+    [0, 2, 0],
+    [0, 3, 0],
+    [1, 1, -1],
+    [-3, 1, 5],
+    [8, 1, 8],
+    [-16, 2, -16],
+];
+
+fn golden_path() -> PathBuf {
+    PathBuf::from(env!("CARGO_MANIFEST_DIR")).join("tests/golden/world_generation.txt")
+}
+
+/// FNV-1a, which unlike `DefaultHasher` is guaranteed to stay the same.
+fn hash_chunk(chunk: &Chunk) -> u64 {
+    let mut hash: u64 = 0xcbf2_9ce4_8422_2325;
+    for pos in chunk.data().extent().iter_points() {
+        hash ^= chunk.data().get(pos).0 as u64;
+        hash = hash.wrapping_mul(0x0100_0000_01b3);
+    }
+    hash
+}
+
+fn generate(seed: i64, chunks: &[[i32; 3]]) -> BTreeMap<[i32; 3], u64> {
+    let mut app = App::new();
+    app.add_plugins(MinimalPlugins)
+        .insert_resource(WorldPreset {
+            seed,
+            ..Default::default()
+        })
+        .add_plugin(ChunkGeneratorPlugin);
+    for pos in chunks.iter() {
+        app.world
+            .spawn()
+            .insert(GeneratingArea::chunk(PointN(*pos)));
+    }
+
+    let start = Instant::now();
+    loop {
+        app.update();
+        let generated = app.world.query::<&Chunk>().iter(&app.world).count();
+        if generated == chunks.len() {
+            break;
+        }
+        assert!(
+            start.elapsed() < Duration::from_secs(300),
+            "Only {} of {} chunks were generated",
+            generated,
+            chunks.len()
+        );
+        thread::sleep(Duration::from_millis(10));
+    }
+
+    app.world
+        .query::<&Chunk>()
+        .iter(&app.world)
+        .map(|chunk| {
+            let pos: Point3i = chunk.pos();
+            (pos.0, hash_chunk(chunk))
+        })
+        .collect()
+}
+
+fn parse_golden(str: &str) -> BTreeMap<[i32; 3], u64> {
+    str.lines()
+        .filter(|line| !line.trim().is_empty())
+        .map(|line| {
+            let mut parts = line.split_whitespace();
+            let mut coordinate = || parts.next().unwrap().parse::<i32>().unwrap();
+            let pos = [coordinate(), coordinate(), coordinate()];
+            let hash = u64::from_str_radix(parts.next().unwrap(), 16).unwrap();
+            (pos, hash)
+        })
+        .collect()
+}
+
+fn format_golden(hashes: &BTreeMap<[i32; 3], u64>) -> String {
+    hashes
+        .iter()
+        .map(|([x, y, z], hash)| format!("{} {} {} {:016x}\n", x, y, z, hash))
+        .collect()
+}
+
+#[test]
+fn generation_is_deterministic() {
+    let first = generate(SEED, &CHUNKS);
+    let second = generate(SEED, &CHUNKS);
+    assert_eq!(first, second);
+}
+
+#[test]
+fn seed_changes_the_world() {
+    let chunks = [[0, 1, 0], [8, 1, 8]];
+    assert_ne!(generate(SEED, &chunks), generate(SEED + 1, &chunks));
+}
+
+#[test]
+fn chunks_match_golden_hashes() {
+    let hashes = generate(SEED, &CHUNKS);
+    let path = golden_path();
+    if std::env::var_os("BLESS").is_some() {
+        fs::create_dir_all(path.parent().unwrap()).unwrap();
+        fs::write(&path, format_golden(&hashes)).unwrap();
+        eprintln!("Wrote golden hashes to {}", path.display());
+        return;
+    }
+
+    let golden = match fs::read_to_string(&path) {
+        Ok(golden) => parse_golden(&golden),
+        Err(err) => panic!(
+            "Can't read {}: {}, run with BLESS=1 to write it",
+            path.display(),
+            err
+        ),
+    };
+    let changed: Vec<_> = CHUNKS
+        .iter()
+        .filter(|pos| golden.get(*pos) != hashes.get(*pos))
+        .collect();
+    assert!(
+        changed.is_empty(),
+        "Chunks {:?} differ from {}, run with BLESS=1 if the change is intended",
+        changed,
+        path.display()
+    );
+}